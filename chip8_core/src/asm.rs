@@ -0,0 +1,466 @@
+// OPCODE ENCODERS
+//
+// The opposite of decoding: encode_* build the raw u16 opcode for one
+// instruction, and asm_* the same opcode as the two bytes it takes up in a
+// ROM, so a test program can be written as
+//
+//     let rom = program(&[asm_set_reg(0, 5), asm_add_reg(0, 1), asm_jump(0x204)]);
+//
+// instead of a list of hex bytes. There's one of each for every pattern
+// opcode_pattern() knows, in the same order. Arguments are masked down to
+// the bits the opcode has room for
+
+pub fn encode_nop() -> u16 {
+    // 0000
+    0x0000
+}
+
+pub fn encode_cls() -> u16 {
+    // 00E0
+    0x00E0
+}
+
+pub fn encode_ret() -> u16 {
+    // 00EE
+    0x00EE
+}
+
+pub fn encode_scroll_down(n: u8) -> u16 {
+    // 00CN
+    0x00C0 | (n & 0xF) as u16
+}
+
+pub fn encode_scroll_up(n: u8) -> u16 {
+    // 00DN
+    0x00D0 | (n & 0xF) as u16
+}
+
+pub fn encode_scroll_right() -> u16 {
+    // 00FB
+    0x00FB
+}
+
+pub fn encode_scroll_left() -> u16 {
+    // 00FC
+    0x00FC
+}
+
+pub fn encode_exit() -> u16 {
+    // 00FD
+    0x00FD
+}
+
+pub fn encode_lores() -> u16 {
+    // 00FE
+    0x00FE
+}
+
+pub fn encode_hires() -> u16 {
+    // 00FF
+    0x00FF
+}
+
+pub fn encode_jump(addr: u16) -> u16 {
+    // 1NNN
+    0x1000 | (addr & 0x0FFF)
+}
+
+pub fn encode_call(addr: u16) -> u16 {
+    // 2NNN
+    0x2000 | (addr & 0x0FFF)
+}
+
+pub fn encode_skip_eq(x: u8, nn: u8) -> u16 {
+    // 3XNN
+    op_xnn(0x3, x, nn)
+}
+
+pub fn encode_skip_ne(x: u8, nn: u8) -> u16 {
+    // 4XNN
+    op_xnn(0x4, x, nn)
+}
+
+pub fn encode_skip_eq_reg(x: u8, y: u8) -> u16 {
+    // 5XY0
+    op_xyn(0x5, x, y, 0x0)
+}
+
+pub fn encode_save_range(x: u8, y: u8) -> u16 {
+    // 5XY2
+    op_xyn(0x5, x, y, 0x2)
+}
+
+pub fn encode_load_range(x: u8, y: u8) -> u16 {
+    // 5XY3
+    op_xyn(0x5, x, y, 0x3)
+}
+
+pub fn encode_set_reg(x: u8, nn: u8) -> u16 {
+    // 6XNN
+    op_xnn(0x6, x, nn)
+}
+
+pub fn encode_add_reg(x: u8, nn: u8) -> u16 {
+    // 7XNN
+    op_xnn(0x7, x, nn)
+}
+
+pub fn encode_copy_reg(x: u8, y: u8) -> u16 {
+    // 8XY0
+    op_xyn(0x8, x, y, 0x0)
+}
+
+pub fn encode_or(x: u8, y: u8) -> u16 {
+    // 8XY1
+    op_xyn(0x8, x, y, 0x1)
+}
+
+pub fn encode_and(x: u8, y: u8) -> u16 {
+    // 8XY2
+    op_xyn(0x8, x, y, 0x2)
+}
+
+pub fn encode_xor(x: u8, y: u8) -> u16 {
+    // 8XY3
+    op_xyn(0x8, x, y, 0x3)
+}
+
+pub fn encode_add_regs(x: u8, y: u8) -> u16 {
+    // 8XY4
+    op_xyn(0x8, x, y, 0x4)
+}
+
+pub fn encode_sub(x: u8, y: u8) -> u16 {
+    // 8XY5
+    op_xyn(0x8, x, y, 0x5)
+}
+
+pub fn encode_shr(x: u8, y: u8) -> u16 {
+    // 8XY6
+    op_xyn(0x8, x, y, 0x6)
+}
+
+pub fn encode_subn(x: u8, y: u8) -> u16 {
+    // 8XY7
+    op_xyn(0x8, x, y, 0x7)
+}
+
+pub fn encode_shl(x: u8, y: u8) -> u16 {
+    // 8XYE
+    op_xyn(0x8, x, y, 0xE)
+}
+
+pub fn encode_skip_ne_reg(x: u8, y: u8) -> u16 {
+    // 9XY0
+    op_xyn(0x9, x, y, 0x0)
+}
+
+pub fn encode_set_i(addr: u16) -> u16 {
+    // ANNN
+    0xA000 | (addr & 0x0FFF)
+}
+
+pub fn encode_jump_v0(addr: u16) -> u16 {
+    // BNNN
+    0xB000 | (addr & 0x0FFF)
+}
+
+pub fn encode_rand(x: u8, nn: u8) -> u16 {
+    // CXNN
+    op_xnn(0xC, x, nn)
+}
+
+pub fn encode_draw(x: u8, y: u8, n: u8) -> u16 {
+    // DXYN
+    op_xyn(0xD, x, y, n)
+}
+
+pub fn encode_skip_key(x: u8) -> u16 {
+    // EX9E
+    op_xnn(0xE, x, 0x9E)
+}
+
+pub fn encode_skip_not_key(x: u8) -> u16 {
+    // EXA1
+    op_xnn(0xE, x, 0xA1)
+}
+
+pub fn encode_long_i() -> u16 {
+    // F000 NNNN, the address goes in the word after this one:
+    // program(&[asm_long_i(), addr.to_be_bytes()])
+    0xF000
+}
+
+pub fn encode_planes(n: u8) -> u16 {
+    // FN01
+    op_xnn(0xF, n, 0x01)
+}
+
+pub fn encode_get_delay(x: u8) -> u16 {
+    // FX07
+    op_xnn(0xF, x, 0x07)
+}
+
+pub fn encode_wait_key(x: u8) -> u16 {
+    // FX0A
+    op_xnn(0xF, x, 0x0A)
+}
+
+pub fn encode_set_delay(x: u8) -> u16 {
+    // FX15
+    op_xnn(0xF, x, 0x15)
+}
+
+pub fn encode_set_sound(x: u8) -> u16 {
+    // FX18
+    op_xnn(0xF, x, 0x18)
+}
+
+pub fn encode_add_i(x: u8) -> u16 {
+    // FX1E
+    op_xnn(0xF, x, 0x1E)
+}
+
+pub fn encode_font(x: u8) -> u16 {
+    // FX29
+    op_xnn(0xF, x, 0x29)
+}
+
+pub fn encode_big_font(x: u8) -> u16 {
+    // FX30
+    op_xnn(0xF, x, 0x30)
+}
+
+pub fn encode_bcd(x: u8) -> u16 {
+    // FX33
+    op_xnn(0xF, x, 0x33)
+}
+
+pub fn encode_store(x: u8) -> u16 {
+    // FX55
+    op_xnn(0xF, x, 0x55)
+}
+
+pub fn encode_load(x: u8) -> u16 {
+    // FX65
+    op_xnn(0xF, x, 0x65)
+}
+
+pub fn encode_save_flags(x: u8) -> u16 {
+    // FX75
+    op_xnn(0xF, x, 0x75)
+}
+
+pub fn encode_load_flags(x: u8) -> u16 {
+    // FX85
+    op_xnn(0xF, x, 0x85)
+}
+
+fn op_xnn(high: u16, x: u8, nn: u8) -> u16 {
+    (high << 12) | ((x & 0xF) as u16) << 8 | nn as u16
+}
+
+fn op_xyn(high: u16, x: u8, y: u8, n: u8) -> u16 {
+    (high << 12) | ((x & 0xF) as u16) << 8 | ((y & 0xF) as u16) << 4 | (n & 0xF) as u16
+}
+
+pub fn asm_nop() -> [u8; 2] {
+    encode_nop().to_be_bytes()
+}
+
+pub fn asm_cls() -> [u8; 2] {
+    encode_cls().to_be_bytes()
+}
+
+pub fn asm_ret() -> [u8; 2] {
+    encode_ret().to_be_bytes()
+}
+
+pub fn asm_scroll_down(n: u8) -> [u8; 2] {
+    encode_scroll_down(n).to_be_bytes()
+}
+
+pub fn asm_scroll_up(n: u8) -> [u8; 2] {
+    encode_scroll_up(n).to_be_bytes()
+}
+
+pub fn asm_scroll_right() -> [u8; 2] {
+    encode_scroll_right().to_be_bytes()
+}
+
+pub fn asm_scroll_left() -> [u8; 2] {
+    encode_scroll_left().to_be_bytes()
+}
+
+pub fn asm_exit() -> [u8; 2] {
+    encode_exit().to_be_bytes()
+}
+
+pub fn asm_lores() -> [u8; 2] {
+    encode_lores().to_be_bytes()
+}
+
+pub fn asm_hires() -> [u8; 2] {
+    encode_hires().to_be_bytes()
+}
+
+pub fn asm_jump(addr: u16) -> [u8; 2] {
+    encode_jump(addr).to_be_bytes()
+}
+
+pub fn asm_call(addr: u16) -> [u8; 2] {
+    encode_call(addr).to_be_bytes()
+}
+
+pub fn asm_skip_eq(x: u8, nn: u8) -> [u8; 2] {
+    encode_skip_eq(x, nn).to_be_bytes()
+}
+
+pub fn asm_skip_ne(x: u8, nn: u8) -> [u8; 2] {
+    encode_skip_ne(x, nn).to_be_bytes()
+}
+
+pub fn asm_skip_eq_reg(x: u8, y: u8) -> [u8; 2] {
+    encode_skip_eq_reg(x, y).to_be_bytes()
+}
+
+pub fn asm_save_range(x: u8, y: u8) -> [u8; 2] {
+    encode_save_range(x, y).to_be_bytes()
+}
+
+pub fn asm_load_range(x: u8, y: u8) -> [u8; 2] {
+    encode_load_range(x, y).to_be_bytes()
+}
+
+pub fn asm_set_reg(x: u8, nn: u8) -> [u8; 2] {
+    encode_set_reg(x, nn).to_be_bytes()
+}
+
+pub fn asm_add_reg(x: u8, nn: u8) -> [u8; 2] {
+    encode_add_reg(x, nn).to_be_bytes()
+}
+
+pub fn asm_copy_reg(x: u8, y: u8) -> [u8; 2] {
+    encode_copy_reg(x, y).to_be_bytes()
+}
+
+pub fn asm_or(x: u8, y: u8) -> [u8; 2] {
+    encode_or(x, y).to_be_bytes()
+}
+
+pub fn asm_and(x: u8, y: u8) -> [u8; 2] {
+    encode_and(x, y).to_be_bytes()
+}
+
+pub fn asm_xor(x: u8, y: u8) -> [u8; 2] {
+    encode_xor(x, y).to_be_bytes()
+}
+
+pub fn asm_add_regs(x: u8, y: u8) -> [u8; 2] {
+    encode_add_regs(x, y).to_be_bytes()
+}
+
+pub fn asm_sub(x: u8, y: u8) -> [u8; 2] {
+    encode_sub(x, y).to_be_bytes()
+}
+
+pub fn asm_shr(x: u8, y: u8) -> [u8; 2] {
+    encode_shr(x, y).to_be_bytes()
+}
+
+pub fn asm_subn(x: u8, y: u8) -> [u8; 2] {
+    encode_subn(x, y).to_be_bytes()
+}
+
+pub fn asm_shl(x: u8, y: u8) -> [u8; 2] {
+    encode_shl(x, y).to_be_bytes()
+}
+
+pub fn asm_skip_ne_reg(x: u8, y: u8) -> [u8; 2] {
+    encode_skip_ne_reg(x, y).to_be_bytes()
+}
+
+pub fn asm_set_i(addr: u16) -> [u8; 2] {
+    encode_set_i(addr).to_be_bytes()
+}
+
+pub fn asm_jump_v0(addr: u16) -> [u8; 2] {
+    encode_jump_v0(addr).to_be_bytes()
+}
+
+pub fn asm_rand(x: u8, nn: u8) -> [u8; 2] {
+    encode_rand(x, nn).to_be_bytes()
+}
+
+pub fn asm_draw(x: u8, y: u8, n: u8) -> [u8; 2] {
+    encode_draw(x, y, n).to_be_bytes()
+}
+
+pub fn asm_skip_key(x: u8) -> [u8; 2] {
+    encode_skip_key(x).to_be_bytes()
+}
+
+pub fn asm_skip_not_key(x: u8) -> [u8; 2] {
+    encode_skip_not_key(x).to_be_bytes()
+}
+
+pub fn asm_long_i() -> [u8; 2] {
+    encode_long_i().to_be_bytes()
+}
+
+pub fn asm_planes(n: u8) -> [u8; 2] {
+    encode_planes(n).to_be_bytes()
+}
+
+pub fn asm_get_delay(x: u8) -> [u8; 2] {
+    encode_get_delay(x).to_be_bytes()
+}
+
+pub fn asm_wait_key(x: u8) -> [u8; 2] {
+    encode_wait_key(x).to_be_bytes()
+}
+
+pub fn asm_set_delay(x: u8) -> [u8; 2] {
+    encode_set_delay(x).to_be_bytes()
+}
+
+pub fn asm_set_sound(x: u8) -> [u8; 2] {
+    encode_set_sound(x).to_be_bytes()
+}
+
+pub fn asm_add_i(x: u8) -> [u8; 2] {
+    encode_add_i(x).to_be_bytes()
+}
+
+pub fn asm_font(x: u8) -> [u8; 2] {
+    encode_font(x).to_be_bytes()
+}
+
+pub fn asm_big_font(x: u8) -> [u8; 2] {
+    encode_big_font(x).to_be_bytes()
+}
+
+pub fn asm_bcd(x: u8) -> [u8; 2] {
+    encode_bcd(x).to_be_bytes()
+}
+
+pub fn asm_store(x: u8) -> [u8; 2] {
+    encode_store(x).to_be_bytes()
+}
+
+pub fn asm_load(x: u8) -> [u8; 2] {
+    encode_load(x).to_be_bytes()
+}
+
+pub fn asm_save_flags(x: u8) -> [u8; 2] {
+    encode_save_flags(x).to_be_bytes()
+}
+
+pub fn asm_load_flags(x: u8) -> [u8; 2] {
+    encode_load_flags(x).to_be_bytes()
+}
+
+pub fn program(instructions: &[[u8; 2]]) -> Vec<u8> {
+    // glues encoded instructions together into a ROM for load_rom()
+    instructions.concat()
+}
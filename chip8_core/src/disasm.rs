@@ -0,0 +1,105 @@
+// DISASSEMBLER
+//
+// Turns opcodes back into (Cowgod style) assembly, e.g. 0x6A12 becomes
+// "LD VA, 0x12". Which opcodes exist comes from opcode_pattern(), so this
+// always agrees with the interpreter about what is and isn't an instruction
+
+use crate::{be_u16, nnn, opcode_pattern};
+
+pub fn disassemble(op: u16) -> String {
+    // Unknown opcodes come out as a raw data word
+    let x = (op & 0x0F00) >> 8;
+    let y = (op & 0x00F0) >> 4;
+    let n = op & 0x000F;
+    let nn = op & 0x00FF;
+    let nnn = nnn(op);
+
+    let pattern = match opcode_pattern(op) {
+        Some(pattern) => pattern,
+        None => return format!("DW {:#06X}", op),
+    };
+
+    match pattern {
+        "0000" => "NOP".to_string(),
+        "00E0" => "CLS".to_string(),
+        "00EE" => "RET".to_string(),
+        "00CN" => format!("SCD {}", n),
+        "00DN" => format!("SCU {}", n),
+        "00FB" => "SCR".to_string(),
+        "00FC" => "SCL".to_string(),
+        "00FD" => "EXIT".to_string(),
+        "00FE" => "LOW".to_string(),
+        "00FF" => "HIGH".to_string(),
+        "1NNN" => format!("JP {:#05X}", nnn),
+        "2NNN" => format!("CALL {:#05X}", nnn),
+        "3XNN" => format!("SE V{:X}, {:#04X}", x, nn),
+        "4XNN" => format!("SNE V{:X}, {:#04X}", x, nn),
+        "5XY0" => format!("SE V{:X}, V{:X}", x, y),
+        "5XY2" => format!("SAVE V{:X} - V{:X}", x, y),
+        "5XY3" => format!("LOAD V{:X} - V{:X}", x, y),
+        "6XNN" => format!("LD V{:X}, {:#04X}", x, nn),
+        "7XNN" => format!("ADD V{:X}, {:#04X}", x, nn),
+        "8XY0" => format!("LD V{:X}, V{:X}", x, y),
+        "8XY1" => format!("OR V{:X}, V{:X}", x, y),
+        "8XY2" => format!("AND V{:X}, V{:X}", x, y),
+        "8XY3" => format!("XOR V{:X}, V{:X}", x, y),
+        "8XY4" => format!("ADD V{:X}, V{:X}", x, y),
+        "8XY5" => format!("SUB V{:X}, V{:X}", x, y),
+        "8XY6" => format!("SHR V{:X}, V{:X}", x, y),
+        "8XY7" => format!("SUBN V{:X}, V{:X}", x, y),
+        "8XYE" => format!("SHL V{:X}, V{:X}", x, y),
+        "9XY0" => format!("SNE V{:X}, V{:X}", x, y),
+        "ANNN" => format!("LD I, {:#05X}", nnn),
+        "BNNN" => format!("JP V0, {:#05X}", nnn),
+        "CXNN" => format!("RND V{:X}, {:#04X}", x, nn),
+        "DXYN" => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+        "EX9E" => format!("SKP V{:X}", x),
+        "EXA1" => format!("SKNP V{:X}", x),
+        "F000" => "LD I, LONG".to_string(),
+        "FN01" => format!("PLANE {}", x),
+        "FX07" => format!("LD V{:X}, DT", x),
+        "FX0A" => format!("LD V{:X}, K", x),
+        "FX15" => format!("LD DT, V{:X}", x),
+        "FX18" => format!("LD ST, V{:X}", x),
+        "FX1E" => format!("ADD I, V{:X}", x),
+        "FX29" => format!("LD F, V{:X}", x),
+        "FX30" => format!("LD HF, V{:X}", x),
+        "FX33" => format!("LD B, V{:X}", x),
+        "FX55" => format!("LD [I], V{:X}", x),
+        "FX65" => format!("LD V{:X}, [I]", x),
+        "FX75" => format!("LD R, V{:X}", x),
+        "FX85" => format!("LD V{:X}, R", x),
+        // opcode_pattern knows about something we don't
+        _ => format!("DW {:#06X}", op),
+    }
+}
+
+pub fn disassemble_rom(rom: &[u8], start: u16) -> Vec<(u16, u16, String)> {
+    // (address, opcode, mnemonic) for every two bytes of the ROM, as if it
+    // was loaded at `start`. Like validate_rom this has no way to tell code
+    // from data, so sprites and such show up as nonsense instructions.
+    // F000 NNNN is the one four byte instruction, so it gets a single line
+    // showing the address it loads. A trailing odd byte is listed on its
+    // own as a data byte
+    let mut listing = Vec::new();
+    let mut offset = 0;
+
+    while offset + 1 < rom.len() {
+        let addr = start.wrapping_add(offset as u16);
+        let op = be_u16(&rom[offset..]);
+        if op == 0xF000 && offset + 3 < rom.len() {
+            let long_addr = be_u16(&rom[offset + 2..]);
+            listing.push((addr, op, format!("LD I, {:#06X}", long_addr)));
+            offset += 4;
+        } else {
+            listing.push((addr, op, disassemble(op)));
+            offset += 2;
+        }
+    }
+    if let Some(byte) = rom.get(offset) {
+        let addr = start.wrapping_add(offset as u16);
+        listing.push((addr, *byte as u16, format!("DB {:#04X}", byte)));
+    }
+
+    listing
+}
@@ -9,17 +9,44 @@ Basic CPU loop:
     3. Execute, which will possible involve modifying our CPU registers or RAM
     4. Move the PC to the next instruction and repeat
 */
-use rand::random;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::VecDeque;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
+mod rom;
+pub use rom::*;
+
+mod disasm;
+pub use disasm::*;
+
+mod asm;
+pub use asm::*;
+
+#[cfg(feature = "octo")]
+mod octo;
 
 pub const SCREEN_WIDTH: usize = 64;
 pub const SCREEN_HEIGHT: usize = 32;
+// SUPER-CHIP high resolution mode, with the default screen size
+pub const HIRES_WIDTH: usize = 128;
+pub const HIRES_HEIGHT: usize = 64;
+
+// Version of this crate, for bug reports along with capabilities()
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
 
 const RAM_SIZE: usize = 4096;
+const MAX_RAM_SIZE: usize = 0x10000;
 const NUM_REGS: usize = 16;
 const STACK_SIZE: usize = 16;
 const NUM_KEYS: usize = 16;
 const START_ADDR: u16 = 0x200;
 const FONTSET_SIZE: usize = 80;
+const BIG_FONTSET_SIZE: usize = 160;
+const NUM_RPL_FLAGS: usize = 16;
+const NUM_PLANES: usize = 2;
 
 // Defines characters 0 through 9, A through F
 const FONTSET: [u8; FONTSET_SIZE] = [
@@ -41,13 +68,384 @@ const FONTSET: [u8; FONTSET_SIZE] = [
     0xF0, 0x80, 0xF0, 0x80, 0x80  // F
 ];
 
+// SUPER-CHIP's 8x10 font, stored in RAM right after the small one.
+// The original only had digits, A through F are there for XO-CHIP
+const BIG_FONTSET: [u8; BIG_FONTSET_SIZE] = [
+    0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C, // 0
+    0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C, // 1
+    0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF, // 2
+    0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C, // 3
+    0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C, // 5
+    0x3E, 0x7C, 0xC0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C, // 6
+    0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60, // 7
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C, // 8
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C, // 9
+    0x3C, 0x7E, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFE, 0xC3, 0xC3, 0xFE, 0xFE, 0xC3, 0xC3, 0xFE, 0xFC, // B
+    0x3C, 0x7E, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0x7E, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0  // F
+];
+
+
+// The CHIP-8 family members we can emulate. Each newer one is a superset
+// of the one before it, instruction-wise
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Platform {
+    // The original COSMAC VIP interpreter
+    Chip8,
+    // SUPER-CHIP 1.1: hires mode, scrolling, big font, RPL flags
+    SuperChip,
+    // Octo's XO-CHIP extensions on top of SUPER-CHIP
+    XoChip,
+}
+
+
+// The different ways BNNN has been implemented. They differ in which
+// register gets added, and in the edge case where that add carries into
+// the high nibble of the address. With V0 = 0x10, V2 = 0x20, VF = 0x02 and
+// 64KB of RAM:
+//   V0:        B234 -> 0x244, BFFF -> 0x100F. The original jump to NNN + V0
+//   Chip48:    B234 -> 0x254, BFFF -> 0x001. CHIP-48 reads it as BXNN,
+//              jumping to XNN + VX where X (the high nibble of the address)
+//              also picks the register. The target is kept to 12 bits, so
+//              a carry out of the high nibble is lost
+//   SuperChip: B234 -> 0x254, BFFF -> 0x1001. The same BXNN reading, but
+//              the carry is kept and the jump can leave the first 4KB
+// With 4KB of RAM the last two agree, since every jump wraps at the end
+// of RAM anyway
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum JumpQuirk {
+    V0,
+    Chip48,
+    SuperChip,
+}
+
+
+// What instructions reading or writing RAM through I (DXYN, FX33, FX55,
+// FX65...) do once the address passes the end of RAM. With 4KB of RAM and
+// I = 0xFFE, a 4 row sprite reads:
+//   Wrap:  0xFFE, 0xFFF, 0x000, 0x001. Round to the start of RAM again
+//   Clamp: 0xFFE, 0xFFF, 0xFFF, 0xFFF. Stuck on the last byte
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MemoryWrap {
+    Wrap,
+    Clamp,
+}
+
+
+// Settings that are fixed when the emulator is built. The defaults give
+// a standard CHIP-8 with 4KB of RAM and none of the quirks turned on.
+// EmuConfig::for_platform() gives the quirk set a platform is known for,
+// and any of the individual flags can still be overridden afterwards
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EmuConfig {
+    // Which instruction set is available
+    pub platform: Platform,
+    // Size of RAM in bytes. XO-CHIP programs expect the full 64KB
+    pub ram_size: usize,
+    // Size of the (low resolution) screen in pixels, for experimenting
+    // with other display shapes. SCHIP's hires mode is always twice as
+    // wide and twice as tall as this
+    pub screen_width: usize,
+    pub screen_height: usize,
+    // FX1E normally wraps I around at 16 bits. When set, I instead stops at
+    // the last byte of RAM so it can never point outside of memory
+    pub i_saturates: bool,
+    // 8XY1, 8XY2 and 8XY3 set VF to 0 afterwards
+    pub vf_reset: bool,
+    // 00E0 also sets VF to 0, clearing the last collision along with the
+    // screen
+    pub cls_resets_vf: bool,
+    // 7XNN stops at 0xFF instead of wrapping around
+    pub add_immediate_saturates: bool,
+    // FX55 and FX65 leave I pointing just past the last register
+    pub load_store_increments_i: bool,
+    // 8XY6 and 8XYE shift VY and store the result in VX, instead of
+    // shifting VX in place
+    pub shift_uses_vy: bool,
+    // How BNNN picks the register it adds to the address, see JumpQuirk
+    pub jump_quirk: JumpQuirk,
+    // What happens to addresses past the end of RAM, see MemoryWrap
+    pub memory_wrap: MemoryWrap,
+    // Sprites are cut off at the edges of the screen instead of wrapping
+    // around to the other side
+    pub clip_sprites: bool,
+    // DXYN waits for the next 60Hz frame before execution continues
+    pub display_wait: bool,
+    // DXYN costs machine cycles per sprite byte drawn, like on the VIP,
+    // instead of a flat cycle_cost(). Only changes what machine_cycles()
+    // counts, and so how much fits into a run_frame()
+    pub vip_draw_timing: bool,
+    // DXYN only ever sets VF to 1 on a collision, and leaves it as it was
+    // otherwise
+    pub dxyn_preserve_vf_on_no_collision: bool,
+    // Tick the timers from inside tick() every cycles_per_timer_tick
+    // cycles, instead of relying on the frontend calling tick_timers() at
+    // 60Hz. Makes timing depend only on the cycle count, for replays
+    pub cycle_driven_timers: bool,
+    pub cycles_per_timer_tick: u32,
+    // Lets run_frame() run at most this many DXYNs per frame, ending the
+    // frame early and leaving the next draw for the following one. Cuts
+    // down on flicker in ROMs that erase and redraw within a frame
+    pub max_draws_per_frame: Option<u32>,
+    // How many of the most recent instruction addresses pc_history()
+    // remembers. 0 turns the history off
+    pub pc_history_len: usize,
+    // After this many 0000s in a row, fire a NopRun trace event. Real
+    // programs hardly ever use 0000, so a long run of them usually means
+    // PC wandered off into empty RAM
+    pub nop_run_threshold: Option<u32>,
+    // Halt (with a RanOffRom trace event) instead of running code past
+    // the end of the loaded ROM. Don't use with ROMs that copy code
+    // somewhere above themselves and jump to it
+    pub halt_on_rom_end: bool,
+}
+
+impl Default for EmuConfig {
+    fn default() -> Self {
+        Self {
+            platform: Platform::Chip8,
+            ram_size: RAM_SIZE,
+            screen_width: SCREEN_WIDTH,
+            screen_height: SCREEN_HEIGHT,
+            i_saturates: false,
+            vf_reset: false,
+            cls_resets_vf: false,
+            add_immediate_saturates: false,
+            load_store_increments_i: false,
+            shift_uses_vy: false,
+            jump_quirk: JumpQuirk::V0,
+            memory_wrap: MemoryWrap::Wrap,
+            clip_sprites: false,
+            display_wait: false,
+            vip_draw_timing: false,
+            dxyn_preserve_vf_on_no_collision: false,
+            cycle_driven_timers: false,
+            // same as the desktop frontend's instructions per frame
+            cycles_per_timer_tick: 10,
+            max_draws_per_frame: None,
+            pc_history_len: 16,
+            nop_run_threshold: None,
+            halt_on_rom_end: false,
+        }
+    }
+}
+
+impl EmuConfig {
+    pub fn for_platform(platform: Platform) -> Self {
+        // the quirk set each platform's programs were written against
+        let defaults = Self::default();
+        match platform {
+            Platform::Chip8 => Self {
+                platform,
+                vf_reset: true,
+                load_store_increments_i: true,
+                shift_uses_vy: true,
+                clip_sprites: true,
+                display_wait: true,
+                ..defaults
+            },
+            Platform::SuperChip => Self {
+                platform,
+                jump_quirk: JumpQuirk::SuperChip,
+                clip_sprites: true,
+                ..defaults
+            },
+            Platform::XoChip => Self {
+                platform,
+                ram_size: MAX_RAM_SIZE,
+                load_store_increments_i: true,
+                shift_uses_vy: true,
+                ..defaults
+            },
+        }
+    }
+}
+
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Chip8Error {
+    // The ROM doesn't fit in RAM between START_ADDR and the end of memory
+    RomTooLarge { size: usize, max: usize },
+    // The opcode belongs to a newer platform than the one being emulated
+    UnsupportedOpcode { op: u16, platform: Platform },
+    // `len` bytes starting at `addr` run past the end of RAM
+    OutOfBounds { addr: usize, len: usize },
+    // The opcode isn't an instruction on any platform
+    UnknownOpcode { op: u16 },
+    // 2NNN with all `max` stack slots already in use
+    StackOverflow { max: usize },
+    // 00EE with nothing on the stack to return to
+    StackUnderflow,
+    // A font that isn't `expected` bytes long (5 per glyph, 0-F)
+    BadFontSize { size: usize, expected: usize },
+    // A memory image too small to hold a program, or too big to address
+    BadRamSize { size: usize },
+    // A display buffer of `size` pixels for a screen of `expected`
+    BadBufferSize { size: usize, expected: usize },
+    // A configured screen with no pixels in it
+    BadScreenSize { width: usize, height: usize },
+}
+
+impl fmt::Display for Chip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Chip8Error::RomTooLarge { size, max } => {
+                write!(f, "ROM is {} bytes but only {} bytes fit in RAM", size, max)
+            },
+            Chip8Error::UnsupportedOpcode { op, platform } => {
+                write!(f, "Opcode {:04X} is not supported on {:?}", op, platform)
+            },
+            Chip8Error::OutOfBounds { addr, len } => {
+                write!(f, "{} bytes at {:#06X} run past the end of RAM", len, addr)
+            },
+            Chip8Error::UnknownOpcode { op } => {
+                write!(f, "Unknown opcode {:04X}", op)
+            },
+            Chip8Error::StackOverflow { max } => {
+                write!(f, "Stack overflow, subroutines nested more than {} deep", max)
+            },
+            Chip8Error::StackUnderflow => {
+                write!(f, "Stack underflow, returned with no subroutine to return from")
+            },
+            Chip8Error::BadFontSize { size, expected } => {
+                write!(f, "Font is {} bytes but should be {}", size, expected)
+            },
+            Chip8Error::BadRamSize { size } => {
+                write!(f, "{} bytes is not a usable RAM size", size)
+            },
+            Chip8Error::BadBufferSize { size, expected } => {
+                write!(f, "Buffer holds {} pixels but the screen is {}", size, expected)
+            },
+            Chip8Error::BadScreenSize { width, height } => {
+                write!(f, "{}x{} is not a usable screen size", width, height)
+            },
+        }
+    }
+}
+
+impl std::error::Error for Chip8Error {}
+
+
+// Just the CPU registers, for debuggers and tests that don't care about
+// RAM or the screen
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct CpuState {
+    pub pc: u16,
+    pub i: u16,
+    pub sp: u16,
+    pub v: [u8; NUM_REGS],
+    pub dt: u8,
+    pub st: u8,
+}
+
+// What a single tick_outcome() did that a frontend might want to react to
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TickOutcome {
+    // Ran a DXYN
+    pub drew: bool,
+    // FX18 started a sound while the sound timer was at zero
+    pub beep_started: bool,
+    // The emulator is halted (00FD, or halt_on_rom_end), now or already
+    pub halted: bool,
+}
+
+
+// Things a debugger might want to know about that don't show up in the
+// machine state. Delivered through the trace callback, if one is set
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DebugEvent {
+    // DXYN turned off a lit pixel. (x, y) is the first one it hit
+    Collision { x: usize, y: usize },
+    // PC is about to run an instruction at `addr`, which is inside a
+    // region marked with mark_data_region()
+    DataExecuted { addr: u16 },
+    // DXYN with the clip_sprites quirk left `pixels` lit sprite pixels
+    // off the edge of the screen
+    Clipped { pixels: u32 },
+    // 2NNN entered the subroutine at `target`, and will come back to
+    // `return_addr`
+    Call { target: u16, return_addr: u16 },
+    // 00EE left a subroutine, going back to `return_addr`
+    Return { return_addr: u16 },
+    // DXYN read `len` bytes of sprite data from `addr`, starting inside
+    // the fonts or the loaded ROM but running off the end of it. Usually
+    // means I was worked out wrong
+    SpriteOverrun { addr: u16, len: usize },
+    // The 0000 at `addr` made nop_run_threshold of them in a row
+    NopRun { addr: u16 },
+    // PC got to `addr`, past the end of the ROM, and halt_on_rom_end
+    // stopped the emulator
+    RanOffRom { addr: u16 },
+}
+
+
+// What undo_last() needs to step back over one tick. Copying all of RAM
+// and the screen every tick would be slow, so registers and the like are
+// copied, but RAM, the screen and the RNG only get recorded as the
+// instruction changes them
+#[derive(Clone)]
+struct Snapshot {
+    pc: u16,
+    // (address, old value) of every byte written, in order
+    ram_writes: Vec<(usize, u8)>,
+    // (plane, index) of every pixel DXYN flipped, unless the whole screen
+    // was saved
+    pixel_flips: Vec<(usize, usize)>,
+    // Both planes as they were, for instructions that change all of the
+    // screen at once. Only valid while screen_saved is set
+    screen_saved: bool,
+    screen: Vec<bool>,
+    plane1: Vec<bool>,
+    selected_planes: u8,
+    hires: bool,
+    v_reg: [u8; NUM_REGS],
+    i_reg: u16,
+    sp: u16,
+    stack: [u16; STACK_SIZE],
+    dt: u8,
+    st: u8,
+    // Saved by CXNN, the only instruction that moves the RNG on
+    rng: Option<StdRng>,
+    machine_cycles: u64,
+    cycles: u64,
+    halted: bool,
+    waiting_for_vblank: bool,
+    waiting_for_key: Option<usize>,
+    rpl_flags: [u8; NUM_RPL_FLAGS],
+    pc_history: VecDeque<u16>,
+    nop_run: u32,
+    opcode_histogram: [u64; 16],
+}
+
 
+type OpcodeHandler = Box<dyn FnMut(&mut Emu, u16)>;
+
+// A user supplied instruction, run for any opcode where op & mask == pattern
+struct CustomOpcode {
+    mask: u16,
+    pattern: u16,
+    handler: OpcodeHandler,
+}
 
 
 pub struct Emu {
     pc: u16,
-    ram: [u8; RAM_SIZE],
-    screen: [bool; SCREEN_WIDTH * SCREEN_HEIGHT],
+    ram: Vec<u8>,
+    screen: Vec<bool>,
+    // XO-CHIP's second bitplane, same size as `screen` (which is plane 0)
+    plane1: Vec<bool>,
+    // Bit N set means drawing and clearing affect plane N, see FN01
+    selected_planes: u8,
+    hires: bool,
+    // What the frontend sees. Drawing only touches `screen` (the back
+    // buffer) and present() copies it over here once the frame is done
+    front_screen: Vec<bool>,
+    front_plane1: Vec<bool>,
+    front_hires: bool,
     v_reg: [u8; NUM_REGS],
     i_reg: u16,
     sp: u16,
@@ -55,14 +453,94 @@ pub struct Emu {
     keys: [bool;NUM_KEYS],
     dt: u8,
     st: u8,
+    rng: StdRng,
+    config: EmuConfig,
+    rom_len: usize,
+    machine_cycles: u64,
+    cycles: u64,
+    timers_paused: bool,
+    beeping: bool,
+    beeps_muted: bool,
+    beep_callback: Option<Box<dyn FnMut(bool)>>,
+    sound_start_callback: Option<Box<dyn FnMut(u8)>>,
+    ram_write_callback: Option<Box<dyn FnMut(u16, u8)>>,
+    trace_callback: Option<Box<dyn FnMut(DebugEvent)>>,
+    halted: bool,
+    waiting_for_vblank: bool,
+    // Set by FX0A to the register the next key press goes into
+    waiting_for_key: Option<usize>,
+    rpl_flags: [u8; NUM_RPL_FLAGS],
+    // The 0-F font load_fonts() copies in, FONTSET unless set_fontset() changed it
+    font: [u8; FONTSET_SIZE],
+    undo: Option<Snapshot>,
+    custom_opcodes: Vec<CustomOpcode>,
+    // Set whenever the back buffer changes, until clear_dirty()
+    dirty: bool,
+    // Whether the last tick ran a DXYN
+    drew: bool,
+    // Whether the last tick changed the back buffer
+    changed: bool,
+    // Whether the last tick started a sound from silence
+    sound_started: bool,
+    // Pixels the last DXYN turned off
+    draw_collisions: u32,
+    // Keys the frontend can actually press, bit N for key N
+    valid_keys: u16,
+    // (start, end) address ranges marked as data, end included
+    data_regions: Vec<(u16, u16)>,
+    // Addresses of the last config.pc_history_len instructions, oldest first
+    pc_history: VecDeque<u16>,
+    // How many 0000s in a row we've just run
+    nop_run: u32,
+    // cycles() when each key last went down
+    key_pressed_at: [Option<u64>; NUM_KEYS],
+    // Instructions run since the last reset, by their first nibble
+    opcode_histogram: [u64; 16],
+    // Inside tick_fast(), which skips events, callbacks and dirty tracking
+    fast: bool,
 }
 
 impl Emu {
     pub fn new() -> Self {
+        Self::with_config(EmuConfig::default())
+    }
+
+    pub fn for_platform(platform: Platform) -> Self {
+        // an emulator set up the way programs for `platform` expect
+        Self::with_config(EmuConfig::for_platform(platform))
+    }
+
+    pub fn with_config(config: EmuConfig) -> Self {
+        // try_with_config() for configs known to be good, like the presets.
+        // Panics on a bad RAM or screen size
+        match Self::try_with_config(config) {
+            Ok(emu) => emu,
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    pub fn try_with_config(config: EmuConfig) -> Result<Self, Chip8Error> {
+        // RAM has to at least hold the font and the start of a program, and
+        // can't be bigger than what a 16-bit address can reach. Use this
+        // for configs that come from a user, e.g. a frontend's settings
+        if config.ram_size <= START_ADDR as usize || config.ram_size > MAX_RAM_SIZE {
+            return Err(Chip8Error::BadRamSize { size: config.ram_size });
+        }
+        if config.screen_width == 0 || config.screen_height == 0 {
+            return Err(Chip8Error::BadScreenSize { width: config.screen_width, height: config.screen_height });
+        }
+        let pixels = config.screen_width * config.screen_height;
+
         let mut new_emu = Self {
             pc: START_ADDR,
-            ram: [0; RAM_SIZE],
-            screen: [false; SCREEN_WIDTH * SCREEN_HEIGHT],
+            ram: vec![0; config.ram_size],
+            screen: vec![false; pixels],
+            plane1: vec![false; pixels],
+            selected_planes: 1,
+            hires: false,
+            front_screen: vec![false; pixels],
+            front_plane1: vec![false; pixels],
+            front_hires: false,
             v_reg: [0; NUM_REGS],
             i_reg: 0,
             sp: 0,
@@ -70,482 +548,3691 @@ impl Emu {
             keys: [false;NUM_KEYS],
             dt: 0,
             st: 0,
+            rng: StdRng::from_entropy(),
+            config,
+            rom_len: 0,
+            machine_cycles: 0,
+            cycles: 0,
+            timers_paused: false,
+            beeping: false,
+            beeps_muted: false,
+            beep_callback: None,
+            sound_start_callback: None,
+            ram_write_callback: None,
+            trace_callback: None,
+            halted: false,
+            waiting_for_vblank: false,
+            waiting_for_key: None,
+            rpl_flags: [0; NUM_RPL_FLAGS],
+            font: FONTSET,
+            undo: None,
+            custom_opcodes: Vec::new(),
+            dirty: false,
+            drew: false,
+            sound_started: false,
+            changed: false,
+            draw_collisions: 0,
+            valid_keys: 0xFFFF,
+            data_regions: Vec::new(),
+            pc_history: VecDeque::with_capacity(config.pc_history_len),
+            nop_run: 0,
+            key_pressed_at: [None; NUM_KEYS],
+            opcode_histogram: [0; 16],
+            fast: false,
         };
-        new_emu.ram[..FONTSET_SIZE].copy_from_slice(&FONTSET);
+        new_emu.load_fonts();
 
-        new_emu
+        Ok(new_emu)
 
     }
 
-    pub fn get_display(&self) -> &[bool] {
-        // passes a pointer to the screen buffer to the frontend
-        &self.screen
+    pub fn new_with_fill(fill: u8) -> Self {
+        // Real hardware doesn't power on with zeroed memory. This fills RAM
+        // past the fonts and the V and I registers with `fill` instead,
+        // which quickly shows up ROMs that (wrongly) expect zeroes
+        let mut emu = Self::new();
+        emu.ram[FONTSET_SIZE + BIG_FONTSET_SIZE..].fill(fill);
+        emu.v_reg = [fill; NUM_REGS];
+        emu.i_reg = u16::from_be_bytes([fill, fill]);
+        emu
     }
 
-    pub fn keypress(&mut self, idx: usize, pressed: bool) {
-        // allows us to set elements of the keys array
-        // frontend will handle actually reading keyboard presses
-        self.keys[idx] = pressed;
+    pub fn active_quirks(&self) -> EmuConfig {
+        // the configuration (platform, RAM size and quirk flags) this
+        // emulator is running with, e.g. to show in a UI or a bug report
+        self.config
     }
 
-    pub fn load(&mut self, data: &[u8]) {
-        // loads the game code into RAM
-        let start = START_ADDR as usize;
-        let end = (START_ADDR as usize) + data.len();
-        self.ram[start..end].copy_from_slice(data);
+    pub fn from_state(state: CpuState, ram: &[u8]) -> Result<Self, Chip8Error> {
+        // builds an emulator with its registers and all of RAM (fonts
+        // included) taken from somewhere else, e.g. to start two different
+        // interpreters off from the same point when fuzzing. RAM is as big
+        // as the image, which has the same limits as EmuConfig::ram_size
+        let mut emu = Self::try_with_config(EmuConfig { ram_size: ram.len(), ..EmuConfig::default() })?;
+        emu.ram.copy_from_slice(ram);
+        emu.restore_cpu_state(state);
+        Ok(emu)
     }
-        
 
-    pub fn reset(&mut self) {
-        // resets the emulator by setting everything back to default values
-        self.pc = START_ADDR; // program counter
-        self.ram = [0; RAM_SIZE];
-        self.screen = [false; SCREEN_WIDTH * SCREEN_HEIGHT];
-        self.v_reg = [0; NUM_REGS];
-        self.i_reg = 0;
-        self.sp = 0; // stack pointer
-        self.stack = [0x200; STACK_SIZE];
-        self.keys = [false;NUM_KEYS];
-        self.dt = 0; // delay timer
-        self.st = 0; // sound timer
-        self.ram[..FONTSET_SIZE].copy_from_slice(&FONTSET);
+    pub fn from_rom(rom: &[u8]) -> Result<Self, Chip8Error> {
+        // builds a fresh emulator with the game already loaded, ready to tick
+        let mut emu = Self::new();
+        emu.load_rom(rom)?;
+        Ok(emu)
     }
 
-    fn push(&mut self, val: u16) {
-        self.stack[self.sp as usize] = val;
-        self.sp += 1;
+    pub fn get_display(&self) -> &[bool] {
+        // passes a pointer to the front screen buffer to the frontend.
+        // it only changes when present() is called, and is display_size()
+        // pixels big
+        &self.front_screen
     }
 
-    fn pop(&mut self) -> u16 {
-        self.sp -= 1;
-        self.stack[self.sp as usize]
+    pub fn display_ascii(&self, on: char, off: char) -> String {
+        // get_display() as text, one line per row of pixels (each ending
+        // in a newline), for terminal frontends and test snapshots
+        let (width, _) = self.display_size();
+        let mut text = String::new();
+        for row in self.front_screen.chunks(width) {
+            text.extend(row.iter().map(|&lit| if lit { on } else { off }));
+            text.push('\n');
+        }
+        text
     }
 
-    pub fn tick(&mut self) {
-        
-        // Fetch
-        let op = self.fetch();
-        
-        // Decode & execute
-        self.execute(op);
+    pub fn copy_display_into(&self, buf: &mut [bool]) -> Result<(), Chip8Error> {
+        // same as get_display(), but copied into the caller's buffer so a
+        // frontend can reuse one every frame. It has to be exactly
+        // display_size() pixels, which changes with the resolution
+        if buf.len() != self.front_screen.len() {
+            return Err(Chip8Error::BadBufferSize { size: buf.len(), expected: self.front_screen.len() });
+        }
+        buf.copy_from_slice(&self.front_screen);
+        Ok(())
     }
 
-
-    fn fetch(&mut self) -> u16 {
-        // get the instruction (opcode) we are about to execute
-        let higher_byte = self.ram[self.pc as usize] as u16;
-        let lower_byte = self.ram[(self.pc + 1) as usize] as u16;
-        let op = (higher_byte << 8) | lower_byte;
-        self.pc += 2;
-        op
+    pub fn packed_display(&self) -> Vec<u8> {
+        // get_display() with 8 pixels to a byte, leftmost pixel in the top
+        // bit, for sending the screen somewhere compactly. A row doesn't
+        // start a new byte, the pixels just run on
+        self.front_screen
+            .chunks(8)
+            .map(|pixels| {
+                pixels.iter().enumerate().fold(0, |byte, (bit, &lit)| byte | ((lit as u8) << (7 - bit)))
+            })
+            .collect()
     }
 
+    pub fn display_delta(&self, previous: &[u8]) -> Vec<(usize, u8)> {
+        // The (index, new byte) of every packed_display() byte that differs from `previous`
+        // usize indices, since a configured screen can pack into more bytes than a u16 counts
+        self.packed_display()
+            .into_iter()
+            .enumerate()
+            .filter(|&(idx, byte)| previous.get(idx) != Some(&byte))
+            .collect()
+    }
 
-    pub fn tick_timers(&mut self) {
-        if self.dt > 0 {
-            self.dt -= 1;
+    pub fn set_pixel(&mut self, x: usize, y: usize, on: bool) -> bool {
+        // Draws straight into the back buffer (plane 0), without DXYN, for
+        // overlays and test harnesses. Shows up after the next present(),
+        // like anything else drawn. Returns false and does nothing if
+        // (x, y) is off screen in the current resolution
+        let idx = match self.pixel_index(x, y) {
+            Some(idx) => idx,
+            None => return false,
+        };
+        if self.screen[idx] != on {
+            self.screen[idx] = on;
+            self.mark_dirty();
         }
+        true
+    }
 
-        if self.st > 0 {
-            if self.st == 1 {
-                // BEEP
-            }
-            self.st -= 1;
+    pub fn get_plane(&self, plane: usize) -> Option<&[bool]> {
+        // front buffer of one XO-CHIP bitplane, same size as get_display()
+        // (which is plane 0). Plane 1 stays blank unless an XO-CHIP ROM
+        // selects it with FN01
+        match plane {
+            0 => Some(&self.front_screen),
+            1 => Some(&self.front_plane1),
+            _ => None,
         }
     }
 
+    pub fn display_size(&self) -> (usize, usize) {
+        // (width, height) of the buffer get_display returns, which depends
+        // on whether the game has switched to hires
+        self.dims_for(self.front_hires)
+    }
 
+    pub fn present(&mut self) {
+        // publishes everything drawn since the last call, so a frontend
+        // never reads a frame that's only partly drawn
+        self.front_screen.clone_from(&self.screen);
+        self.front_plane1.clone_from(&self.plane1);
+        self.front_hires = self.hires;
+    }
 
-    fn execute(&mut self, op: u16) {
-        let digit1 = (op & 0xF000) >> 12;
-        let digit2 = (op & 0x0F00) >> 8;
-        let digit3 = (op & 0x00F0) >> 4;
-        let digit4 = op & 0x000F;
+    pub fn is_dirty(&self) -> bool {
+        // whether anything has been drawn since the last clear_dirty(), so
+        // a frontend can skip redrawing frames that didn't change
+        self.dirty
+    }
 
+    pub fn clear_dirty(&mut self) {
+        self.dirty = false;
+    }
 
-        match (digit1, digit2, digit3, digit4) {
-            
-            // NOP: do nothing
-            (0,0,0,0) => return,
+    pub fn display_changed(&self) -> bool {
+        // whether the most recent tick on its own changed the screen. Unlike
+        // is_dirty() this starts over every tick and is never cleared by hand
+        self.changed
+    }
 
-            // 00E0 - Clear screen (CLS)
-            (0,0,0xE,0) => {
-                self.screen = [false; SCREEN_WIDTH * SCREEN_HEIGHT];
-            },
+    pub fn last_draw_collisions(&self) -> u32 {
+        // how many lit pixels the most recent DXYN turned off. VF only says
+        // whether this was more than zero
+        self.draw_collisions
+    }
 
-            // 00EE - Retrun from subroutine (RET)
-            (0,0,0xE,0xE) => {
-                let ret_addr = self.pop();
-                self.pc = ret_addr;
-            },
+    fn mark_dirty(&mut self) {
+        if self.fast {
+            return;
+        }
+        self.dirty = true;
+        self.changed = true;
+    }
 
-            // 1NNN - Jump
-            // Anything starting with 1, but ending with any three digits 
-            // The other 3 digits are used as parameters
-            (1,_,_,_) => {
-                let nnn = op & 0xFFF;
-                self.pc = nnn;
-            },
+    pub fn run_until_draw(&mut self, max_cycles: usize) -> bool {
+        // Ticks until the next DXYN runs, giving up after max_cycles or on
+        // an error. Returns whether a draw happened, e.g. to grab the first
+        // frame a ROM renders for a screenshot
+        for _ in 0..max_cycles {
+            if self.tick().is_err() {
+                return false;
+            }
+            if self.drew {
+                return true;
+            }
+        }
+        false
+    }
 
-            // 2NNN - Call subroutine
-            // Opposite of RET. Add current PC to the stack, then
-            // jump to the given address
-            // 2, followed by the 3 parameters for where to jump to
-            (2,_,_,_) => {
-               let nnn = op & 0xFFF;
-               self.push(self.pc);
-               self.pc = nnn;
-            },
-            
-            // 3XNN - Skip next if VX == NN
-            // Assembly equivalent of an if else block a
-            (3,_,_,_) => {
-                let x = digit2 as usize;
-                let nn = (op & 0xFF) as u8;
-                if self.v_reg[x] == nn {
-                    // skipping the next opcode is the same as skipping
-                    // PC ahead by 2 bytes
-                    self.pc += 2;
-                }
-            },
+    pub fn step_out(&mut self, max_cycles: usize) -> bool {
+        // Debugger "step out": ticks until the subroutine we're in returns,
+        // which is when sp drops below where it is now. Returns whether
+        // that happened within max_cycles. Outside of any subroutine
+        // there's nothing to step out of, so this doesn't run at all
+        let depth = self.sp;
+        if depth == 0 {
+            return false;
+        }
+        for _ in 0..max_cycles {
+            if self.tick().is_err() {
+                return false;
+            }
+            if self.sp < depth {
+                return true;
+            }
+        }
+        false
+    }
 
-            // 4XNN - Skip next if VX != NN
-            (4,_,_,_) => {
-                let x = digit2 as usize;
-                let nn = (op & 0xFF) as u8;
-                if self.v_reg[x] != nn {
-                    // skipping the next opcode is the same as skipping
-                    // PC ahead by 2 bytes
-                    self.pc += 2;
-                }
-            },
+    pub fn step_over(&mut self, max_cycles: usize) -> bool {
+        // Debugger "step over": a single step, except that a 2NNN call is
+        // run all the way through until it comes back to the instruction
+        // after it. Returns whether we got there within max_cycles (which
+        // includes the call itself) and without an error
+        let is_call = self.peek_opcode() >> 12 == 0x2 && !self.is_waiting() && !self.halted;
+        if max_cycles == 0 || self.tick().is_err() {
+            return false;
+        }
+        if is_call {
+            return self.step_out(max_cycles - 1);
+        }
+        true
+    }
 
-            // 5XY0 - Skip next if VX == VY
-            (5,_,_,0) => {
-                let x = digit2 as usize;
-                let y = digit3 as usize;
-                if self.v_reg[x] == self.v_reg[y] {
-                    self.pc += 2;
-                }
-            },
+    pub fn is_waiting_for_key(&self) -> bool {
+        // true while FX0A is blocked waiting for a key press
+        self.waiting_for_key.is_some()
+    }
 
-            // 6XNN - VX = NN
-            // Sets the VX register to the given value
-            (6,_,_,_) => {
-                let x = digit2 as usize;
-                let nn = (op & 0xFF) as u8;
-                self.v_reg[x] = nn;
-            },
+    fn is_waiting(&self) -> bool {
+        // whether the CPU is stalled on FX0A or DXYN, rather than running
+        self.waiting_for_key.is_some() || self.waiting_for_vblank
+    }
 
-            // 7XNN - VX += NN
-            // Adds given value to the VX reigster
-            (7,_,_,_) => {
-                let x = digit2 as usize;
-                let nn = (op & 0xFF) as u8;
-                // use wrapping_add to avoid rust panics at overflows
-                self.v_reg[x] = self.v_reg[x].wrapping_add(nn); 
-            },
+    pub fn any_key_pressed(&self) -> bool {
+        // whether anything on the keypad is held down, e.g. for a frontend
+        // that pauses until there's input
+        self.first_pressed_key().is_some()
+    }
 
-            // 8XY0 - VX = VY
-            // Set the VX register to the value of VY
-            (8,_,_,0) => {
-                let x = digit2 as usize;
-                let y = digit3 as usize;
-                self.v_reg[x] = self.v_reg[y];
-            },
-            
-            // 8XY1 - Bitwise OR operation (VX |= VY)
-            (8,_,_,1) => {
-                let x = digit2 as usize;
-                let y = digit3 as usize;
-                self.v_reg[x] |= self.v_reg[y];
-            },
-            
-            // 8XY2 - Bitwise AND operation (VX &= VY)
-            (8,_,_,2) => {
-                let x = digit2 as usize;
-                let y = digit3 as usize;
-                self.v_reg[x] &= self.v_reg[y];
-            },
-            
-            // 8XY3 - Bitwise XOR operation (VX ^= VY)
-            (8,_,_,3) => {
-                let x = digit2 as usize;
-                let y = digit3 as usize;
-                self.v_reg[x] ^= self.v_reg[y];
-            },
+    fn first_pressed_key(&self) -> Option<u8> {
+        // the lowest numbered key held down, which is what FX0A picks
+        (0..NUM_KEYS as u8).find(|&key| self.keys[key as usize])
+    }
 
-            // 8XY4 - VX += VY
-            // Does the operation then sets the Flag register to indicate whether
-            // or not an overflow occured (if overflow, flag =1, 0 if not)
-            (8,_,_,4) => {
-                let x = digit2 as usize;
-                let y = digit3 as usize;
+    pub fn is_halted(&self) -> bool {
+        // true once the game has exited with 00FD
+        self.halted
+    }
 
-                let (new_vx, carry) = self.v_reg[x].overflowing_add(self.v_reg[y]);
-                let new_vf = if carry { 1 } else { 0 };
+    pub fn is_idle(&self) -> bool {
+        // true when the ROM has nothing left to do: it's halted, or the
+        // next instruction is a 1NNN jumping to itself, which is how most
+        // programs end. 1NNN only reaches the first 4KB, so past 0xFFF
+        // nothing jumps to itself like this
+        let op = self.peek_opcode();
+        self.halted || (op >> 12 == 1 && nnn(op) as usize == self.pc as usize)
+    }
 
-                self.v_reg[x] = new_vx;
-                self.v_reg[0xF] = new_vf;
-            },
+    fn peek_opcode(&self) -> u16 {
+        // the opcode at PC, without fetching it
+        self.read_u16(self.pc as usize)
+    }
 
-            // 8XY5 - VX -= VY
-            // Same as the last one except subtraction instead of addtion.
-            (8,_,_,5) => {
-                let x = digit2 as usize;
-                let y = digit3 as usize;
-             
-                let(new_vx, borrow) = self.v_reg[x].overflowing_sub(self.v_reg[y]);
-                let new_vf = if borrow { 0 } else { 1 };
+    pub fn cycles_to_idle(&mut self, max_cycles: usize) -> Option<u64> {
+        // Ticks until is_idle() and returns how many cycles that took, or
+        // None if the ROM hit an error or still wasn't idle after
+        // max_cycles. For timing how long an intro takes to settle
+        for ran in 0..=max_cycles {
+            if self.is_idle() {
+                return Some(ran as u64);
+            }
+            if ran == max_cycles || self.tick().is_err() {
+                break;
+            }
+        }
+        None
+    }
 
-                self.v_reg[x] = new_vx;
-                self.v_reg[0xF] = new_vf;
-            },
+    fn screen_dims(&self) -> (usize, usize) {
+        // size of the back buffer in the current resolution
+        self.dims_for(self.hires)
+    }
 
-            // 8XY6 - Single right shift of VX (VX >>= 1)
-            // bit that is dropped off is stored in the VF register
-            (8,_,_,6) => {
-                let x = digit2 as usize;
-                let lsb = self.v_reg[x] & 1;
-                self.v_reg[x] >>= 1;
-                self.v_reg[0xF] = lsb;
-            },
+    fn dims_for(&self, hires: bool) -> (usize, usize) {
+        // screen size in lores or hires mode. Both come from the configured
+        // geometry, 64x32 and 128x64 by default
+        let (width, height) = (self.config.screen_width, self.config.screen_height);
+        if hires {
+            (width * 2, height * 2)
+        } else {
+            (width, height)
+        }
+    }
 
-            // 8XY7 - VX = VY - VX
-            // similar to 8XY5 but in opposite direction
-            (8,_,_,7) => {
-                let x = digit2 as usize;
-                let y = digit3 as usize;
-                
-                let (new_vx, borrow) = self.v_reg[y].overflowing_sub(self.v_reg[x]);
-                let new_vf = if borrow { 0 } else { 1 };
+    fn pixel_index(&self, x: usize, y: usize) -> Option<usize> {
+        // index of (x, y) in the back buffer, or None if it's off screen
+        let (width, height) = self.screen_dims();
+        if x >= width || y >= height {
+            return None;
+        }
+        let idx = x + width * y;
+        debug_assert!(idx < self.screen.len(), "screen buffer doesn't match the resolution");
+        Some(idx)
+    }
 
-                self.v_reg[x] = new_vx;
-                self.v_reg[0xF] = new_vf;
-            },
+    fn set_hires(&mut self, hires: bool) {
+        // switching resolution also clears the screen
+        self.save_screen_for_undo();
+        self.hires = hires;
+        let (width, height) = self.screen_dims();
+        self.screen = vec![false; width * height];
+        self.plane1 = vec![false; width * height];
+        self.mark_dirty();
+    }
 
-            // 8XYE - Single left shift of VX (VX <<= 1)
-            // Store the overflowed value in the flag register
-            (8,_,_,0xE) => {
-                let x = digit2 as usize;
-                let msb = (self.v_reg[x] >> 7) & 1;
-                self.v_reg[x] <<= 1;
-                self.v_reg[0xF] = msb;
-            },
+    pub fn set_fontset(&mut self, font: &[u8]) -> Result<(), Chip8Error> {
+        // Swaps in different shapes for the 0-F glyphs FX29 points at. Same
+        // layout as FONTSET: 5 rows per glyph, 80 bytes in all. It stays
+        // set across reset(), like the config does
+        if font.len() != FONTSET_SIZE {
+            return Err(Chip8Error::BadFontSize { size: font.len(), expected: FONTSET_SIZE });
+        }
+        self.font.copy_from_slice(font);
+        self.ram[..FONTSET_SIZE].copy_from_slice(font);
+        Ok(())
+    }
 
-            // 9XY0 - Skip if VX != VY
-            // Same as 5XY0 but with an inequality
-            (9,_,_,0) => {
-                let x = digit2 as usize;
-                let y = digit2 as usize;
-                if self.v_reg[x] != self.v_reg[y] {
-                    self.pc += 2;
-                }
-            },
+    pub fn font_bytes(&self) -> &[u8] {
+        // the 0-F font as it is in RAM right now, built in or from
+        // set_fontset(), or whatever a ROM has scribbled over it
+        &self.ram[..FONTSET_SIZE]
+    }
 
-            // ANNN - I = NNN
-            (0xA,_,_,_) => {
-                let nnn = op & 0xFFF;
-                self.i_reg = nnn;
-            },
-        
-            // BNNN - Jump to V0 + NNN
-            (0xB,_,_,_) => {
-                let nnn = op & 0xFFF;
-                self.pc = (self.v_reg[0] as u16) + nnn;
-            },
+    pub fn big_font_addr(&self) -> u16 {
+        // where the SCHIP big font starts in RAM, right after the small
+        // one. Glyph N is the 10 bytes at big_font_addr() + N * 10, which
+        // is where FX30 points I
+        FONTSET_SIZE as u16
+    }
 
-            // CXNN - VC = rand() & NN
-            // Random number generator, which is AND'd which is then
-            // AND'd with the lower 8-bits of the opcode
-            (0xC,_,_,_) => {
-                let x = digit2 as usize;
-                let nn = (op & 0xFF) as u8;
-                let rng: u8 = random(); // u8 so random() knows what to generate
-                self.v_reg[x] = rng & nn;
-            },
+    fn load_fonts(&mut self) {
+        // both fonts live at the very start of RAM, below the program
+        self.ram[..FONTSET_SIZE].copy_from_slice(&self.font);
+        self.ram[FONTSET_SIZE..FONTSET_SIZE + BIG_FONTSET_SIZE].copy_from_slice(&BIG_FONTSET);
+    }
 
-            // DXYN - Draw Sprite
-            (0xD,_,_,_) => {
-                // Get the (x, y) coords for our sprite
-                let x_coord = self.v_reg[digit2 as usize] as u16;
-                let y_coord = self.v_reg[digit3 as usize] as u16;
-                
-                // The last digit determines how many rows high our sprite is 
-                let num_rows = digit4;
+    fn reg_range(x: u16, y: u16) -> Box<dyn Iterator<Item = usize>> {
+        // register indices from X to Y inclusive, counting down if X > Y
+        let (x, y) = (x as usize, y as usize);
+        if x <= y {
+            Box::new(x..=y)
+        } else {
+            Box::new((y..=x).rev())
+        }
+    }
 
-                // Keep track if any pixels were flipped
-                let mut flipped = false;
+    fn plane_mut(&mut self, plane: usize) -> &mut Vec<bool> {
+        // back buffer of bitplane 0 (the only one before XO-CHIP) or 1
+        if plane == 0 {
+            &mut self.screen
+        } else {
+            &mut self.plane1
+        }
+    }
 
-                // iterate over each row of our sprite
-                for y_line in 0..num_rows {
-                    // Determine which memory address our row's data is stored
-                    let addr = self.i_reg + y_line as u16;
-                    let pixels = self.ram[addr as usize];
-                    // Iterate over each column in our row
-                    for x_line in 0..8 { 
-                        // Use a mask to fetch current pixel's bit. Only flip if a 1
-                        if (pixels & (0b10000000 >> x_line)) != 0 {
-                            // Sprites should wrap around the screen, so apply modulo
-                            let x = (x_coord + x_line) as usize % SCREEN_WIDTH;
-                            let y = (y_coord + y_line) as usize % SCREEN_HEIGHT;
-
-                            // Get our pixel's index for our 1D screen array
-                            let idx = x + SCREEN_WIDTH * y;
-                            // Check if we're about to flip the pixel and set
-                            flipped |= self.screen[idx];
-                            self.screen[idx] ^= true;
-                        }
-                    }
-                }
-                if flipped {
-                    self.v_reg[0xF] = 1;
+    fn selected_plane_indices(&self) -> impl Iterator<Item = usize> {
+        let selected = self.selected_planes;
+        (0..NUM_PLANES).filter(move |plane| (selected >> plane) & 1 == 1)
+    }
+
+    fn scroll_up(&mut self, rows: usize) {
+        // moves every row of the selected planes up, the rows scrolled in
+        // at the bottom are blank
+        self.save_screen_for_undo();
+        let (width, height) = self.screen_dims();
+        let rows = rows.min(height);
+        for plane in self.selected_plane_indices() {
+            let screen = self.plane_mut(plane);
+            screen.copy_within(rows * width.., 0);
+            screen[(height - rows) * width..].fill(false);
+        }
+        self.mark_dirty();
+    }
+
+    fn scroll_down(&mut self, rows: usize) {
+        // moves every row of the selected planes down, the rows scrolled in
+        // at the top are blank
+        self.save_screen_for_undo();
+        let (width, height) = self.screen_dims();
+        let rows = rows.min(height);
+        for plane in self.selected_plane_indices() {
+            let screen = self.plane_mut(plane);
+            screen.copy_within(..(height - rows) * width, rows * width);
+            screen[..rows * width].fill(false);
+        }
+        self.mark_dirty();
+    }
+
+    fn scroll_horizontal(&mut self, cols: isize) {
+        // positive scrolls right, negative left. Pixels scrolled off the
+        // edge are lost and the columns scrolled in are blank. Only the
+        // selected planes move
+        self.save_screen_for_undo();
+        let (width, _) = self.screen_dims();
+        let shift = cols.unsigned_abs().min(width);
+        for plane in self.selected_plane_indices() {
+            for row in self.plane_mut(plane).chunks_mut(width) {
+                if cols > 0 {
+                    row.copy_within(..width - shift, shift);
+                    row[..shift].fill(false);
                 } else {
-                    self.v_reg[0xF] = 0;
+                    row.copy_within(shift.., 0);
+                    row[width - shift..].fill(false);
                 }
-            },
+            }
+        }
+        self.mark_dirty();
+    }
 
-            // EX9E - Skip if Key Pressed
-            // Skips to next instruction if the index stored in VX is pressed
-            (0xE,_,9,0xE) => {
-               let x = digit2 as usize;
-               let vx = self.v_reg[x];
-               let key = self.keys[vx as usize];
-               if key {
-                   self.pc += 2;
-               }
-            },
+    fn instruction_cost(&self, op: u16) -> u32 {
+        // cycle_cost(), except that with vip_draw_timing a DXYN costs more
+        // the more sprite bytes it has to go through, same as on the VIP,
+        // up to a whole frame
+        if !self.config.vip_draw_timing || op & 0xF000 != 0xD000 {
+            return cycle_cost(op);
+        }
+        let bytes = match op & 0xF {
+            0 if self.schip_enabled() => 32,
+            rows => rows as u32,
+        };
+        let planes = self.selected_plane_indices().count() as u32;
+        (cycle_cost(op) + bytes * planes * VIP_DRAW_BYTE_COST).min(VIP_CYCLES_PER_FRAME)
+    }
 
-            // EXA1 - Skip if key not pressed
-            (0xE,_,0xA,1) => {
-                let x = digit2 as usize;
-                let vx = self.v_reg[x];
-                let key = self.keys[vx as usize];
-                if !key {
-                    self.pc += 2;
-                }
-            },
+    fn schip_enabled(&self) -> bool {
+        // SUPER-CHIP instructions are there on SCHIP and everything after it
+        self.config.platform >= Platform::SuperChip
+    }
 
-            // FX07 - VX = DT
-            // Stores the timer value into one of of the V registers
-            (0xF,_,0,7) => {
-                let x = digit2 as usize;
-                self.v_reg[x] = self.dt;
-            },
+    pub fn keypress(&mut self, idx: usize, pressed: bool) {
+        // allows us to set elements of the keys array
+        // frontend will handle actually reading keyboard presses
+        if pressed && !self.keys[idx] {
+            self.key_pressed_at[idx] = Some(self.cycles);
+        }
+        self.keys[idx] = pressed;
+    }
 
-            // FX0A - Wait for key press (blocking)
-            (0xF,_,0,0xA) => {
-                let x = digit2 as usize;
-                let mut pressed = false;
-                for i in 0..self.keys.len() {
-                    if self.keys[i] {
-                        self.v_reg[x] = i as u8;
-                        pressed = true;
-                        break;
-                    }
-                }
-                if !pressed {
-                    // Redo opcode
-                    self.pc -= 2;
-                }
-            },
+    pub fn key_pressed_at(&self, idx: usize) -> Option<u64> {
+        // the cycle count (see cycles()) when key idx last went down, or
+        // None if it hasn't been pressed since the last reset
+        self.key_pressed_at.get(idx).copied().flatten()
+    }
 
-            // FX15 - DT = VX
-            // Resets the delay timer to a value from V register
-            (0xF,_,1,5) => {
-                let x = digit2 as usize;
-                self.dt = self.v_reg[x];
-            },
+    pub fn pressed_keys(&self) -> Vec<u8> {
+        // lists every key currently held down, lowest index first
+        let mut pressed = Vec::new();
+        for (i, key) in self.keys.iter().enumerate() {
+            if *key {
+                pressed.push(i as u8);
+            }
+        }
+        pressed
+    }
 
+    pub fn keys_bitmask(&self) -> u16 {
+        // packs the keypad into a u16, bit N set means key N is down
+        let mut mask = 0;
+        for (i, pressed) in self.keys.iter().enumerate() {
+            if *pressed {
+                mask |= 1 << i;
+            }
+        }
+        mask
+    }
 
-            // FX18 - ST = VX
-            // Store value from V register into Sound Timer
-            (0xF,_,1,8) => {
-                let x = digit2 as usize;
-                self.st = self.v_reg[x];
-            },
+    pub fn set_keys_bitmask(&mut self, mask: u16) {
+        // inverse of keys_bitmask, sets the whole keypad at once
+        for i in 0..NUM_KEYS {
+            self.keypress(i, (mask >> i) & 1 == 1);
+        }
+    }
 
-            // FX1E - I += VX
-            // Takes value stored in VX and adds it to I register
-            // Rolls over to zero if overflow 
-            (0xF,_,1,0xE) => {
-                let x = digit2 as usize;
-                let vx = self.v_reg[x] as u16;
-                self.i_reg = self.i_reg.wrapping_add(vx);
-            },
+    pub fn set_valid_keys(&mut self, mask: u16) {
+        // for frontends that can't produce all 16 keys. EX9E and EXA1 treat
+        // any key with its bit clear as never pressed, whatever the keypad
+        // says. Defaults to every key
+        self.valid_keys = mask;
+    }
 
-            // FX29 - Set I to Font Address
-            // Note that since we are storing fonts at the start of RAM
-            // and they take up 5 bytes each, their location is simply their
-            // value multiplied by 5
-            (0xF,_,2,9) => {
-                let x = digit2 as usize;
-                let c = self.v_reg[x] as u16;
-                self.i_reg = c *5;
-            },
+    fn key_down(&self, key: u8) -> bool {
+        // Only the low nibble picks a key, the VIP's keypad latch ignored
+        // the rest, so EX9E/EXA1 with VX > 0xF can't index off the end
+        let key = key & 0xF;
+        self.keys[key as usize] && (self.valid_keys >> key) & 1 == 1
+    }
 
-            // FX33 - I = BCD of VX
-            // Store the binary coded decimal of a number in the VX register
-            // into the I register
-            (0xF,_,3,3) => {
-                let x = digit2 as usize;
-                let vx = self.v_reg[x] as f32;
+    pub fn load_rom(&mut self, data: &[u8]) -> Result<(), Chip8Error> {
+        // loads the game code into RAM
+        let start = START_ADDR as usize;
+        let max = self.ram.len() - start;
+        if data.len() > max {
+            return Err(Chip8Error::RomTooLarge { size: data.len(), max });
+        }
+        let end = start + data.len();
+        self.ram[start..end].copy_from_slice(data);
+        self.rom_len = data.len();
+        Ok(())
+    }
 
-                // Fetch the hundreds digit by dividing by 100 and tossing the decimal
-                let hundreds = (vx / 100.0).floor() as u8;
-                // Fetch the tens digit by dividing by 10, toss ones digit and decimal
-                let tens = ((vx / 10.0) % 10.0).floor() as u8;
-                // Fetch the ones digit by tossing the hundreds and the tens
-                let ones = (vx % 10.0) as u8;
-                
-                self.ram[self.i_reg as usize] = hundreds;
-                self.ram[(self.i_reg + 1) as usize] = tens;
-                self.ram[(self.i_reg + 2) as usize] = ones
-            },
+    pub fn load_segments(&mut self, segments: &[(u16, &[u8])]) -> Result<(), Chip8Error> {
+        // Loads several pieces of code or data at their own addresses,
+        // e.g. a small loader and the game it starts, and points PC at the
+        // first one. Nothing is written unless every segment fits. As far
+        // as loaded_rom() is concerned the ROM runs from START_ADDR to the
+        // end of the highest segment
+        for &(addr, data) in segments {
+            if addr as usize + data.len() > self.ram.len() {
+                return Err(Chip8Error::OutOfBounds { addr: addr as usize, len: data.len() });
+            }
+        }
 
-            // FX55 - Store V0 through VX into I
-            (0xF,_,5,5) => {
-                let x = digit2 as usize;
-                let i = self.i_reg as usize;
-                for idx in 0..=x {
-                    self.ram[i + idx] = self.v_reg[idx];
-                }
-            },
+        let start = START_ADDR as usize;
+        for &(addr, data) in segments {
+            let addr = addr as usize;
+            self.ram[addr..addr + data.len()].copy_from_slice(data);
+            self.rom_len = self.rom_len.max((addr + data.len()).saturating_sub(start));
+        }
+        if let Some(&(addr, _)) = segments.first() {
+            self.set_pc(addr as usize);
+        }
+        Ok(())
+    }
 
-            // FX65 - Load I into V0 through VX
-            (0xF,_,6,5) => {
-                let x = digit2 as usize;
-                let i = self.i_reg as usize;
-                for idx in 0..=x {
-                    self.v_reg[idx] = self.ram[i + idx]
-                }
-            },
+    pub fn swap_rom(&mut self, data: &[u8]) -> Result<(), Chip8Error> {
+        // Replaces the loaded ROM with a new build of it while it runs, for
+        // livecoding. Registers, PC, the stack, timers and the screen are
+        // all left as they were (call reset_registers() too for a clean
+        // start), so PC may well end up pointing into the middle of an
+        // instruction that moved. Whatever the old ROM left in RAM past
+        // the end of the new one is zeroed
+        let start = START_ADDR as usize;
+        let max = self.ram.len() - start;
+        if data.len() > max {
+            return Err(Chip8Error::RomTooLarge { size: data.len(), max });
+        }
+        self.ram[start..start + self.rom_len].fill(0);
+        self.load_rom(data)
+    }
 
-            // Everything else. Should never hit this but ya know 
-            (_,_,_,_) => unimplemented!("Unimplemented opcode: {}", op),
-        };
+    pub fn auto_configure(&mut self, rom: &[u8]) -> Result<(), Chip8Error> {
+        // Zero config start: guesses the ROM's platform with
+        // detect_platform(), switches to that platform's quirk preset,
+        // resets and loads the ROM. Only a heuristic, so for ROMs it gets
+        // wrong build the emulator with_config() and load_rom() instead
+        self.config = EmuConfig::for_platform(detect_platform(rom));
+        self.reset();
+        self.load_rom(rom)
+    }
+
+    pub fn loaded_rom(&self) -> &[u8] {
+        // the region of RAM the last load_rom() wrote the game into
+        let start = START_ADDR as usize;
+        &self.ram[start..start + self.rom_len]
     }
+        
 
+    pub fn reset(&mut self) {
+        // resets the emulator by setting everything back to default values.
+        // Settings stay as they are: the config (quirks, platform, RAM
+        // size), a custom font, callbacks, custom opcodes, the valid key
+        // mask and the RPL flags. Registers, RAM, the screen, timers, the
+        // stack and keys all go back to how new() left them
+        self.reset_registers();
+        self.ram = vec![0; self.config.ram_size];
+        self.set_hires(false);
+        self.selected_planes = 1;
+        self.present();
+        self.stack = [0x200; STACK_SIZE];
+        self.keys = [false;NUM_KEYS];
+        self.key_pressed_at = [None; NUM_KEYS];
+        self.reset_timers();
+        self.rom_len = 0;
+        self.machine_cycles = 0;
+        self.cycles = 0;
+        self.halted = false;
+        self.waiting_for_vblank = false;
+        self.waiting_for_key = None;
+        self.undo = None;
+        self.pc_history.clear();
+        self.nop_run = 0;
+        self.opcode_histogram = [0; 16];
+        self.load_fonts();
+    }
 
+    pub fn reset_timers(&mut self) {
+        // zeroes both timers, which also stops any beep in progress
+        self.dt = 0; // delay timer
+        self.st = 0; // sound timer
+        self.set_beeping(false);
+    }
 
-} // END Emu impl
+    pub fn reset_registers(&mut self) {
+        // puts the CPU registers back to their power-on values but leaves
+        // RAM and the screen alone. A pending FX0A or display wait belongs
+        // to the old PC, so it goes too
+        self.pc = START_ADDR; // program counter
+        self.v_reg = [0; NUM_REGS];
+        self.i_reg = 0;
+        self.sp = 0; // stack pointer
+        self.waiting_for_vblank = false;
+        self.waiting_for_key = None;
+    }
 
+    pub fn seed_rng(&mut self, seed: u64) {
+        // reseeds the random number generator used by CXNN so that runs
+        // are reproducible, e.g. for netplay or replays
+        self.rng = StdRng::seed_from_u64(seed);
+    }
 
+    pub fn state_hash(&self) -> u64 {
+        // hashes the full machine state so two emulators can cheaply
+        // check that they are still in sync. That includes what the CPU is
+        // waiting on, since a parked FX0A and a running PC can otherwise
+        // look the same
+        let mut hasher = DefaultHasher::new();
+        self.pc.hash(&mut hasher);
+        self.ram.hash(&mut hasher);
+        self.screen.hash(&mut hasher);
+        self.plane1.hash(&mut hasher);
+        self.selected_planes.hash(&mut hasher);
+        self.hires.hash(&mut hasher);
+        self.v_reg.hash(&mut hasher);
+        self.i_reg.hash(&mut hasher);
+        self.sp.hash(&mut hasher);
+        self.stack.hash(&mut hasher);
+        self.keys.hash(&mut hasher);
+        self.dt.hash(&mut hasher);
+        self.st.hash(&mut hasher);
+        self.waiting_for_key.hash(&mut hasher);
+        self.waiting_for_vblank.hash(&mut hasher);
+        self.halted.hash(&mut hasher);
+        self.rpl_flags.hash(&mut hasher);
+        hasher.finish()
+    }
 
+    pub fn screen_hash(&self) -> u64 {
+        // hashes just what get_display() shows, so a frontend can spot a
+        // screen that hasn't changed in a long time (e.g. a crashed ROM)
+        // without keeping old frames around
+        let mut hasher = DefaultHasher::new();
+        self.front_hires.hash(&mut hasher);
+        self.front_screen.hash(&mut hasher);
+        self.front_plane1.hash(&mut hasher);
+        hasher.finish()
+    }
 
+    fn push(&mut self, val: u16) -> Result<(), Chip8Error> {
+        // sp is always between 0 and STACK_SIZE, so it's the number of
+        // slots in use and the index of the next free one
+        if self.sp as usize >= STACK_SIZE {
+            return Err(Chip8Error::StackOverflow { max: STACK_SIZE });
+        }
+        self.stack[self.sp as usize] = val;
+        self.sp += 1;
+        Ok(())
+    }
 
+    fn pop(&mut self) -> Result<u16, Chip8Error> {
+        if self.sp == 0 {
+            return Err(Chip8Error::StackUnderflow);
+        }
+        self.sp -= 1;
+        Ok(self.stack[self.sp as usize])
+    }
 
+    pub fn pc_history(&self) -> Vec<u16> {
+        // addresses of the most recent instructions, oldest first, so after
+        // an error the last entry is the one that failed and the rest show
+        // how we got there
+        self.pc_history.iter().copied().collect()
+    }
 
+    pub fn call_stack(&self) -> &[u16] {
+        // return addresses of the subroutines we're in, outermost first
+        &self.stack[..self.sp as usize]
+    }
 
+    pub fn stack_depth(&self) -> usize {
+        // how many subroutines deep we are
+        self.sp as usize
+    }
 
+    pub fn stack_remaining(&self) -> usize {
+        // how many more calls fit before 2NNN overflows the stack
+        STACK_SIZE - self.sp as usize
+    }
 
+    pub fn tick(&mut self) -> Result<(), Chip8Error> {
+        // Nothing to do once the game has exited
+        if self.halted {
+            return Ok(());
+        }
 
+        // Remember where we were so undo_last() can step back over this.
+        // The old snapshot's buffers get reused so this doesn't allocate
+        let mut snapshot = match self.undo.take() {
+            Some(snapshot) => snapshot,
+            None => self.snapshot(),
+        };
+        self.save_snapshot(&mut snapshot);
+        self.undo = Some(snapshot);
 
+        self.drew = false;
+        self.changed = false;
+        self.sound_started = false;
 
+        if !self.is_waiting() {
+            if self.in_data_region(self.pc) {
+                self.emit(DebugEvent::DataExecuted { addr: self.pc });
+            }
 
+            if self.config.pc_history_len > 0 {
+                if self.pc_history.len() == self.config.pc_history_len {
+                    self.pc_history.pop_front();
+                }
+                self.pc_history.push_back(self.pc);
+            }
+        }
 
+        self.run_cycle()
+    }
 
+    pub fn tick_outcome(&mut self) -> Result<TickOutcome, Chip8Error> {
+        // tick(), but also says what happened during it, so a frontend
+        // doesn't need to poll is_halted() and friends after every cycle
+        if self.halted {
+            return Ok(TickOutcome { halted: true, ..TickOutcome::default() });
+        }
+        self.tick()?;
+        Ok(TickOutcome {
+            drew: self.drew,
+            beep_started: self.sound_started,
+            halted: self.halted,
+        })
+    }
 
+    pub fn tick_fast(&mut self, n: usize) -> Result<(), Chip8Error> {
+        // Runs n cycles with all the debugging machinery switched off, for
+        // headless benchmarks: no trace or RAM write callbacks, no dirty
+        // flag, no undo snapshot and no PC history or data region checks.
+        // Sound isn't debugging, so the sound start callback still fires.
+        // Registers, RAM and the screen come out the same as n tick()s.
+        // undo_last() has nothing to go back to afterwards
+        self.undo = None;
+        self.fast = true;
+        let mut result = Ok(());
+        for _ in 0..n {
+            if self.halted {
+                break;
+            }
+            result = self.run_cycle();
+            if result.is_err() {
+                break;
+            }
+        }
+        self.fast = false;
+        result
+    }
 
+    fn run_cycle(&mut self) -> Result<(), Chip8Error> {
+        // the part of a tick that actually runs the machine
 
+        // While FX0A is waiting for a key, or DXYN on the display (see the
+        // display_wait quirk), the cycle still passes, it just doesn't
+        // execute anything
+        if let Some(x) = self.waiting_for_key {
+            if let Some(key) = self.first_pressed_key() {
+                self.v_reg[x] = key;
+                self.waiting_for_key = None;
+            }
+        } else if !self.waiting_for_vblank {
+            if self.config.halt_on_rom_end && self.pc as usize >= START_ADDR as usize + self.rom_len {
+                self.halted = true;
+                self.emit(DebugEvent::RanOffRom { addr: self.pc });
+                return Ok(());
+            }
 
+            // Fetch
+            let op = self.fetch();
+            self.opcode_histogram[(op >> 12) as usize] += 1;
+            
+            // Decode & execute
+            self.execute(op)?;
+            self.machine_cycles += self.instruction_cost(op) as u64;
+        }
+
+        self.cycles += 1;
+        if self.config.cycle_driven_timers
+            && self.cycles.is_multiple_of(self.config.cycles_per_timer_tick.max(1) as u64)
+        {
+            self.tick_timers();
+        }
+        Ok(())
+    }
+
+    pub fn register_opcode<F: FnMut(&mut Emu, u16) + 'static>(&mut self, mask: u16, pattern: u16, handler: F) {
+        // Adds a new instruction for experimenting with extensions. It is
+        // only consulted for opcodes the interpreter doesn't already know,
+        // and the first registered match wins. The handler runs after PC
+        // has moved past the opcode, like any other instruction
+        self.custom_opcodes.push(CustomOpcode { mask, pattern, handler: Box::new(handler) });
+    }
+
+    fn run_custom_opcode(&mut self, op: u16) -> bool {
+        // Returns whether a custom handler took care of op. The handlers are
+        // moved out while one runs so it can have &mut self
+        let mut handlers = std::mem::take(&mut self.custom_opcodes);
+        let found = handlers.iter_mut().find(|c| op & c.mask == c.pattern);
+        let ran = match found {
+            Some(custom) => {
+                (custom.handler)(self, op);
+                true
+            },
+            None => false,
+        };
+        // Keep anything the handler registered while it ran
+        handlers.append(&mut self.custom_opcodes);
+        self.custom_opcodes = handlers;
+        ran
+    }
+
+    pub fn supports_opcode(&self, op: u16) -> bool {
+        // Whether execute would run op with the current platform and build,
+        // without running it. Custom opcodes count too, but only for
+        // opcodes the interpreter doesn't already know, same as execute
+        match opcode_platform(op) {
+            Some(needs) => needs <= self.config.platform && platform_built(needs),
+            None => self.custom_opcodes.iter().any(|c| op & c.mask == c.pattern),
+        }
+    }
+
+    pub fn cpu_state(&self) -> CpuState {
+        CpuState {
+            pc: self.pc,
+            i: self.i_reg,
+            sp: self.sp,
+            v: self.v_reg,
+            dt: self.dt,
+            st: self.st,
+        }
+    }
+
+    pub fn registers(&self) -> [u8; NUM_REGS] {
+        // a copy of V0 through VF, for a register panel
+        self.v_reg
+    }
+
+    pub fn restore_cpu_state(&mut self, state: CpuState) {
+        // puts the registers back the way cpu_state() found them. RAM,
+        // the stack contents and the screen are left alone. An sp past
+        // the top of the stack is clamped to it
+        self.set_pc(state.pc as usize);
+        self.i_reg = state.i;
+        self.sp = state.sp.min(STACK_SIZE as u16);
+        self.v_reg = state.v;
+        self.dt = state.dt;
+        self.st = state.st;
+    }
+
+    pub fn undo_last(&mut self) -> bool {
+        // Reverts the most recent tick. Only one step is kept, so this
+        // returns false if there is nothing (left) to undo. Changes made
+        // from outside the tick (ram_mut(), write_at_i() and so on) stay
+        match self.undo.take() {
+            Some(snapshot) => {
+                self.restore_snapshot(&snapshot);
+                true
+            },
+            None => false,
+        }
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            pc: self.pc,
+            ram_writes: Vec::new(),
+            pixel_flips: Vec::new(),
+            screen_saved: false,
+            screen: Vec::new(),
+            plane1: Vec::new(),
+            selected_planes: self.selected_planes,
+            hires: self.hires,
+            v_reg: self.v_reg,
+            i_reg: self.i_reg,
+            sp: self.sp,
+            stack: self.stack,
+            dt: self.dt,
+            st: self.st,
+            rng: None,
+            machine_cycles: self.machine_cycles,
+            cycles: self.cycles,
+            halted: self.halted,
+            waiting_for_vblank: self.waiting_for_vblank,
+            waiting_for_key: self.waiting_for_key,
+            rpl_flags: self.rpl_flags,
+            pc_history: self.pc_history.clone(),
+            nop_run: self.nop_run,
+            opcode_histogram: self.opcode_histogram,
+        }
+    }
+
+    fn save_snapshot(&self, snapshot: &mut Snapshot) {
+        // same as snapshot(), but reuses an existing one's buffers
+        snapshot.pc = self.pc;
+        snapshot.ram_writes.clear();
+        snapshot.pixel_flips.clear();
+        snapshot.screen_saved = false;
+        snapshot.selected_planes = self.selected_planes;
+        snapshot.hires = self.hires;
+        snapshot.v_reg = self.v_reg;
+        snapshot.i_reg = self.i_reg;
+        snapshot.sp = self.sp;
+        snapshot.stack = self.stack;
+        snapshot.dt = self.dt;
+        snapshot.st = self.st;
+        snapshot.rng = None;
+        snapshot.machine_cycles = self.machine_cycles;
+        snapshot.cycles = self.cycles;
+        snapshot.halted = self.halted;
+        snapshot.waiting_for_vblank = self.waiting_for_vblank;
+        snapshot.waiting_for_key = self.waiting_for_key;
+        snapshot.rpl_flags = self.rpl_flags;
+        snapshot.pc_history.clone_from(&self.pc_history);
+        snapshot.nop_run = self.nop_run;
+        snapshot.opcode_histogram = self.opcode_histogram;
+    }
+
+    fn restore_snapshot(&mut self, snapshot: &Snapshot) {
+        // Undo the recorded changes newest first, so a byte written twice
+        // ends up with its oldest value
+        self.pc = snapshot.pc;
+        for &(addr, old) in snapshot.ram_writes.iter().rev() {
+            self.ram[addr] = old;
+        }
+        if snapshot.screen_saved {
+            self.screen.clone_from(&snapshot.screen);
+            self.plane1.clone_from(&snapshot.plane1);
+        } else {
+            for &(plane, idx) in snapshot.pixel_flips.iter().rev() {
+                self.plane_mut(plane)[idx] ^= true;
+            }
+        }
+        self.selected_planes = snapshot.selected_planes;
+        self.mark_dirty();
+        self.hires = snapshot.hires;
+        self.v_reg = snapshot.v_reg;
+        self.i_reg = snapshot.i_reg;
+        self.sp = snapshot.sp;
+        self.stack = snapshot.stack;
+        self.dt = snapshot.dt;
+        self.st = snapshot.st;
+        if let Some(rng) = &snapshot.rng {
+            self.rng.clone_from(rng);
+        }
+        self.machine_cycles = snapshot.machine_cycles;
+        self.cycles = snapshot.cycles;
+        self.halted = snapshot.halted;
+        self.waiting_for_vblank = snapshot.waiting_for_vblank;
+        self.waiting_for_key = snapshot.waiting_for_key;
+        self.rpl_flags = snapshot.rpl_flags;
+        self.pc_history.clone_from(&snapshot.pc_history);
+        self.nop_run = snapshot.nop_run;
+        self.opcode_histogram = snapshot.opcode_histogram;
+    }
+
+    fn save_screen_for_undo(&mut self) {
+        // called before an instruction changes the whole screen at once
+        // (scrolling, clearing, switching resolution), where recording
+        // pixel by pixel would cost more than a copy. One instruction
+        // never both does that and flips pixels, so there are no flips
+        // recorded yet to worry about
+        if let Some(undo) = self.undo.as_mut() {
+            if !undo.screen_saved {
+                undo.screen.clone_from(&self.screen);
+                undo.plane1.clone_from(&self.plane1);
+                undo.screen_saved = true;
+            }
+        }
+    }
+
+    fn flip_pixel(&mut self, plane: usize, idx: usize) {
+        // DXYN's one way of changing the screen, recorded for undo_last()
+        if let Some(undo) = self.undo.as_mut() {
+            if !undo.screen_saved {
+                undo.pixel_flips.push((plane, idx));
+            }
+        }
+        self.plane_mut(plane)[idx] ^= true;
+    }
+
+    pub fn run_turbo(&mut self, frames: usize, ipf: usize) -> Result<(), Chip8Error> {
+        // Fast-forwards `frames` frames of `ipf` instructions each, timers
+        // included, as fast as the host can go. Beeps in between would just
+        // be noise, so the beep callback only hears about it if the sound
+        // ends up in a different state than it started in
+        let was_beeping = self.beeping;
+        self.beeps_muted = true;
+
+        let mut result = Ok(());
+        'frames: for _ in 0..frames {
+            for _ in 0..ipf {
+                if let Err(e) = self.tick() {
+                    result = Err(e);
+                    break 'frames;
+                }
+            }
+            if !self.config.cycle_driven_timers {
+                self.tick_timers();
+            }
+        }
+
+        self.beeps_muted = false;
+        if self.beeping != was_beeping {
+            let beeping = self.beeping;
+            if let Some(cb) = self.beep_callback.as_mut() {
+                cb(beeping);
+            }
+        }
+        self.present();
+        result
+    }
+
+    pub fn opcode_histogram(&self) -> [u64; 16] {
+        // how many instructions of each family have run since the last
+        // reset, indexed by the opcode's first nibble (so [0xD] is every
+        // DXYN). For profiling which instructions a ROM spends its time on
+        self.opcode_histogram
+    }
+
+    pub fn cycles(&self) -> u64 {
+        // number of times the CPU has ticked since the last reset
+        self.cycles
+    }
+
+    pub fn run_frame(&mut self, cycle_budget: u32) -> Result<(), Chip8Error> {
+        // Runs one 60Hz frame worth of instructions. Rather than a fixed
+        // instruction count, keep executing until the instructions' combined
+        // cycle cost uses up the budget, then tick the timers once and
+        // present the finished frame
+        let start = self.machine_cycles;
+        let mut draws = 0;
+        while self.machine_cycles - start < cycle_budget as u64 {
+            // The rest of the frame is lost if the CPU has stopped
+            if self.halted || self.waiting_for_vblank {
+                break;
+            }
+            // FX0A only notices a key on a tick, and a waiting tick costs
+            // no cycles, so give it one and spend the rest of the frame
+            // waiting if nothing is pressed yet
+            if self.waiting_for_key.is_some() {
+                self.tick()?;
+                if self.waiting_for_key.is_some() {
+                    break;
+                }
+                continue;
+            }
+            // or if the next instruction would be one draw too many
+            if let Some(max) = self.config.max_draws_per_frame {
+                if draws >= max && self.peek_opcode() >> 12 == 0xD {
+                    break;
+                }
+            }
+            self.tick()?;
+            if self.drew {
+                draws += 1;
+            }
+        }
+        // tick() already took care of the timers if they're cycle driven
+        if !self.config.cycle_driven_timers {
+            self.tick_timers();
+        }
+        self.present();
+        Ok(())
+    }
+
+    pub fn machine_cycles(&self) -> u64 {
+        // total cost of everything executed so far, see cycle_cost()
+        self.machine_cycles
+    }
+
+    pub fn tick_synced(&mut self, remote_keys: u16) -> Result<(), Chip8Error> {
+        // Lockstep tick for netplay. The keypad agreed on over the network
+        // (both players' keys for this step) replaces the local one before
+        // executing, so as long as both sides feed the same inputs and share
+        // an RNG seed they stay in the exact same state
+        self.set_keys_bitmask(remote_keys);
+        self.tick()
+    }
+
+    pub fn run_scripted(&mut self, steps: &[(usize, u16)]) -> Vec<u64> {
+        // Runs a script of (cycles, keys_bitmask) steps, holding the keys
+        // down for that many cycles, and returns state_hash() after each
+        // step. With a fixed seed_rng() the hashes only depend on the ROM
+        // and the script, so a test can compare them against known values.
+        // If the ROM hits an error the script stops there, and the result
+        // is shorter than `steps`
+        let mut hashes = Vec::with_capacity(steps.len());
+        for &(cycles, keys) in steps {
+            for _ in 0..cycles {
+                if self.tick_synced(keys).is_err() {
+                    return hashes;
+                }
+            }
+            hashes.push(self.state_hash());
+        }
+        hashes
+    }
+
+
+    fn fetch(&mut self) -> u16 {
+        // get the instruction (opcode) we are about to execute
+        let op = self.read_u16(self.pc as usize);
+        self.advance_pc();
+        op
+    }
+
+    fn read_u16(&self, addr: usize) -> u16 {
+        // Big endian like everything else (see be_u16), wrapping around
+        // at the end of RAM
+        be_u16(&[self.ram[self.ram_index(addr)], self.ram[self.ram_index(addr + 1)]])
+    }
+
+    fn skip_next(&mut self) {
+        // skips over the next instruction, which on XO-CHIP might be the
+        // 4 byte F000 NNNN
+        let long = self.config.platform >= Platform::XoChip && self.read_u16(self.pc as usize) == 0xF000;
+        self.advance_pc();
+        if long {
+            self.advance_pc();
+        }
+    }
+
+
+    fn advance_pc(&mut self) {
+        // PC wrap policy: the program counter always moves through RAM
+        // modulo its size. An instruction at the very last address is
+        // followed by the one at 0x000 rather than overflowing (or running
+        // past 0x0FFF on a 4KB machine). Fetch, the skip instructions and
+        // BNNN all go through this so they agree on what happens at the top
+        self.set_pc(self.pc as usize + 2);
+    }
+
+    fn set_pc(&mut self, addr: usize) {
+        self.pc = (addr % self.ram.len()) as u16;
+    }
+
+    pub fn instruction_iter(&self) -> impl Iterator<Item = (u16, u16)> + '_ {
+        // (address, opcode) of what's coming up from PC onwards, without
+        // running any of it, for a disassembly view that follows along.
+        // Just reads straight through, so follows no jumps, and stops at
+        // the end of RAM instead of wrapping. F000 comes out on its own and
+        // the address word after it is skipped, since that's not an
+        // instruction
+        let mut addr = self.pc as usize;
+        std::iter::from_fn(move || {
+            if addr + 1 >= self.ram.len() {
+                return None;
+            }
+            let op = be_u16(&self.ram[addr..]);
+            let item = (addr as u16, op);
+            addr += if op == 0xF000 { 4 } else { 2 };
+            Some(item)
+        })
+    }
+
+    pub fn sprite_grid(&self, addr: u16, rows: usize) -> Vec<Vec<bool>> {
+        // Expands `rows` bytes of sprite data starting at addr into rows of
+        // 8 pixels, the same way DXYN would draw them. Meant for sprite
+        // editors and viewers
+        (0..rows)
+            .map(|row| {
+                let pixels = self.ram[self.ram_index(addr as usize + row)] as u16;
+                (0..8).map(|col| sprite_pixel(pixels, 8, col)).collect()
+            })
+            .collect()
+    }
+
+    pub fn ram(&self) -> &[u8] {
+        // all of RAM, fonts at 0 and the ROM at 0x200, for hex viewers
+        &self.ram
+    }
+
+    pub fn dump_ram(&self) -> Vec<u8> {
+        // an owned copy of ram(), to save to disk or hand to another tool
+        self.ram.clone()
+    }
+
+    pub fn ram_mut(&mut self) -> &mut [u8] {
+        // all of RAM, writable. Careful: this goes around everything that
+        // normally watches memory, so the RAM write callback doesn't fire,
+        // undo won't know about it, and overwriting the fonts or the
+        // running code changes what the ROM does from the next tick on
+        &mut self.ram
+    }
+
+    pub fn ram_at_i(&self, len: usize) -> &[u8] {
+        // the `len` bytes I points at, e.g. the sprite DXYN is about to
+        // draw. Cut short if it would run past the end of RAM
+        let start = (self.i_reg as usize).min(self.ram.len());
+        let end = (start + len).min(self.ram.len());
+        &self.ram[start..end]
+    }
+
+    pub fn write_at_i(&mut self, data: &[u8]) -> Result<(), Chip8Error> {
+        // overwrites RAM starting at I, for debuggers poking at sprite data
+        let start = self.i_reg as usize;
+        if start + data.len() > self.ram.len() {
+            return Err(Chip8Error::OutOfBounds { addr: start, len: data.len() });
+        }
+        self.ram[start..start + data.len()].copy_from_slice(data);
+        Ok(())
+    }
+
+    pub fn set_ram_write_callback(&mut self, cb: Box<dyn FnMut(u16, u8)>) {
+        // cb gets the (address, value) of every byte an instruction writes
+        // to RAM, handy for working out how a ROM lays out its data
+        self.ram_write_callback = Some(cb);
+    }
+
+    pub fn set_trace_callback(&mut self, cb: Box<dyn FnMut(DebugEvent)>) {
+        // cb gets every DebugEvent as it happens
+        self.trace_callback = Some(cb);
+    }
+
+    pub fn mark_data_region(&mut self, start: u16, end: u16) {
+        // Marks start..=end as data (sprites, tables...) rather than code.
+        // Every instruction fetched from in there fires a DataExecuted
+        // event, which catches jumps that land in the wrong place.
+        // Regions stay marked across reset()
+        self.data_regions.push((start, end));
+    }
+
+    fn in_data_region(&self, addr: u16) -> bool {
+        self.data_regions.iter().any(|&(start, end)| (start..=end).contains(&addr))
+    }
+
+    fn sprite_overruns(&self, len: usize) -> bool {
+        // whether `len` bytes from I start inside the fonts or the ROM but
+        // don't end there. Sprites anywhere else could have been written
+        // at runtime, so there's nothing to check them against
+        let start = self.i_reg as usize;
+        let end = start + len;
+        let fonts = 0..FONTSET_SIZE + BIG_FONTSET_SIZE;
+        let rom = START_ADDR as usize..START_ADDR as usize + self.rom_len;
+        [fonts, rom].iter().any(|region| region.contains(&start) && end > region.end)
+    }
+
+    fn emit(&mut self, event: DebugEvent) {
+        if self.fast {
+            return;
+        }
+        if let Some(cb) = self.trace_callback.as_mut() {
+            cb(event);
+        }
+    }
+
+    fn write_ram(&mut self, addr: usize, val: u8) {
+        // every instruction that writes to RAM should go through here so
+        // the write callback sees it
+        let addr = self.ram_index(addr);
+        if let Some(undo) = self.undo.as_mut() {
+            undo.ram_writes.push((addr, self.ram[addr]));
+        }
+        self.ram[addr] = val;
+        if self.fast {
+            return;
+        }
+        if let Some(cb) = self.ram_write_callback.as_mut() {
+            cb(addr as u16, val);
+        }
+    }
+
+    fn ram_index(&self, addr: usize) -> usize {
+        // Addresses past the end of the configured RAM wrap back around
+        // to the start, or stick at the end, per the memory_wrap policy.
+        // Either way never off the end of the buffer
+        match self.config.memory_wrap {
+            MemoryWrap::Wrap => addr % self.ram.len(),
+            MemoryWrap::Clamp => addr.min(self.ram.len() - 1),
+        }
+    }
+
+
+    pub fn set_timers_paused(&mut self, paused: bool) {
+        // freezes the delay and sound timers while the CPU keeps running,
+        // e.g. when single stepping in a debugger
+        self.timers_paused = paused;
+    }
+
+    pub fn tick_timers(&mut self) {
+        // This is the 60Hz "vblank", so a DXYN waiting on it can finish
+        self.waiting_for_vblank = false;
+
+        if self.timers_paused {
+            return;
+        }
+
+        if self.dt > 0 {
+            self.dt -= 1;
+        }
+
+        // The tone plays for as long as ST is nonzero
+        self.set_beeping(self.st > 0);
+        if self.st > 0 {
+            self.st -= 1;
+        }
+    }
+
+    pub fn set_beep_callback(&mut self, cb: Box<dyn FnMut(bool)>) {
+        // cb gets true once when the tone should start and false once when
+        // it should stop, rather than being polled every frame
+        self.beep_callback = Some(cb);
+    }
+
+    pub fn set_sound_start_callback(&mut self, cb: Box<dyn FnMut(u8)>) {
+        // cb gets the sound timer's new value the moment FX18 starts a
+        // sound from silence, a little ahead of the beep callback (which
+        // waits for tick_timers), so audio can get a buffer ready
+        self.sound_start_callback = Some(cb);
+    }
+
+    fn set_beeping(&mut self, beeping: bool) {
+        // only tell the callback about edges, not the steady state
+        if beeping != self.beeping {
+            self.beeping = beeping;
+            if self.beeps_muted {
+                return;
+            }
+            if let Some(cb) = self.beep_callback.as_mut() {
+                cb(beeping);
+            }
+        }
+    }
+
+
+
+    fn execute(&mut self, op: u16) -> Result<(), Chip8Error> {
+        // Instructions from a newer platform than ours, or from one this
+        // build left out, are rejected up front, so the arms below never
+        // need to check
+        if let Some(needs) = opcode_platform(op) {
+            if needs > self.config.platform || !platform_built(needs) {
+                return Err(Chip8Error::UnsupportedOpcode { op, platform: self.config.platform });
+            }
+        }
+
+        let digit1 = (op & 0xF000) >> 12;
+        let digit2 = (op & 0x0F00) >> 8;
+        let digit3 = (op & 0x00F0) >> 4;
+        let digit4 = op & 0x000F;
+
+        if op != 0 {
+            self.nop_run = 0;
+        }
+
+        match (digit1, digit2, digit3, digit4) {
+            
+            // NOP: do nothing
+            // Other than keep an eye out for running through empty RAM
+            (0,0,0,0) => {
+                self.nop_run += 1;
+                if Some(self.nop_run) == self.config.nop_run_threshold {
+                    let addr = (self.pc as usize + self.ram.len() - 2) % self.ram.len();
+                    self.emit(DebugEvent::NopRun { addr: addr as u16 });
+                }
+            },
+
+            // 00E0 - Clear screen (CLS)
+            // On XO-CHIP only the selected planes are cleared
+            (0,0,0xE,0) => {
+                self.save_screen_for_undo();
+                for plane in self.selected_plane_indices() {
+                    self.plane_mut(plane).fill(false);
+                }
+                self.mark_dirty();
+                if self.config.cls_resets_vf {
+                    self.v_reg[0xF] = 0;
+                }
+            },
+
+            // 00CN - Scroll down N rows (SCHIP)
+            (0,0,0xC,_) => {
+                self.scroll_down(digit4 as usize);
+            },
+
+            // 00DN - Scroll up N rows (XO-CHIP)
+            (0,0,0xD,_) => {
+                self.scroll_up(digit4 as usize);
+            },
+
+            // 00FB - Scroll right 4 pixels (SCHIP)
+            (0,0,0xF,0xB) => {
+                self.scroll_horizontal(4);
+            },
+
+            // 00FC - Scroll left 4 pixels (SCHIP)
+            (0,0,0xF,0xC) => {
+                self.scroll_horizontal(-4);
+            },
+
+            // 00FD - Exit the interpreter (SCHIP)
+            (0,0,0xF,0xD) => {
+                self.halted = true;
+            },
+
+            // 00FE - Switch to lores, 64x32 (SCHIP)
+            (0,0,0xF,0xE) => {
+                self.set_hires(false);
+            },
+
+            // 00FF - Switch to hires, 128x64 (SCHIP)
+            (0,0,0xF,0xF) => {
+                self.set_hires(true);
+            },
+
+            // 00EE - Retrun from subroutine (RET)
+            (0,0,0xE,0xE) => {
+                let ret_addr = self.pop()?;
+                self.pc = ret_addr;
+                self.emit(DebugEvent::Return { return_addr: ret_addr });
+            },
+
+            // 1NNN - Jump
+            // Anything starting with 1, but ending with any three digits 
+            // The other 3 digits are used as parameters
+            (1,_,_,_) => {
+                self.set_pc(nnn(op) as usize);
+            },
+
+            // 2NNN - Call subroutine
+            // Opposite of RET. Add current PC to the stack, then
+            // jump to the given address
+            // 2, followed by the 3 parameters for where to jump to
+            (2,_,_,_) => {
+               let return_addr = self.pc;
+               self.push(return_addr)?;
+               self.set_pc(nnn(op) as usize);
+               self.emit(DebugEvent::Call { target: self.pc, return_addr });
+            },
+            
+            // 3XNN - Skip next if VX == NN
+            // Assembly equivalent of an if else block a
+            (3,_,_,_) => {
+                let x = digit2 as usize;
+                let nn = (op & 0xFF) as u8;
+                if self.v_reg[x] == nn {
+                    // skipping the next opcode is the same as skipping
+                    // PC ahead by 2 bytes
+                    self.skip_next();
+                }
+            },
+
+            // 4XNN - Skip next if VX != NN
+            (4,_,_,_) => {
+                let x = digit2 as usize;
+                let nn = (op & 0xFF) as u8;
+                if self.v_reg[x] != nn {
+                    // skipping the next opcode is the same as skipping
+                    // PC ahead by 2 bytes
+                    self.skip_next();
+                }
+            },
+
+            // 5XY0 - Skip next if VX == VY
+            (5,_,_,0) => {
+                let x = digit2 as usize;
+                let y = digit3 as usize;
+                if self.v_reg[x] == self.v_reg[y] {
+                    self.skip_next();
+                }
+            },
+
+            // 5XY2 - Save VX through VY to I (XO-CHIP)
+            // Unlike FX55 this doesn't touch I. If X > Y the registers are
+            // written out in reverse order
+            (5,_,_,2) => {
+                let i = self.i_reg as usize;
+                for (offset, reg) in Self::reg_range(digit2, digit3).enumerate() {
+                    self.write_ram(i + offset, self.v_reg[reg]);
+                }
+            },
+
+            // 5XY3 - Load VX through VY from I (XO-CHIP)
+            (5,_,_,3) => {
+                let i = self.i_reg as usize;
+                for (offset, reg) in Self::reg_range(digit2, digit3).enumerate() {
+                    self.v_reg[reg] = self.ram[self.ram_index(i + offset)];
+                }
+            },
+
+            // 6XNN - VX = NN
+            // Sets the VX register to the given value
+            (6,_,_,_) => {
+                let x = digit2 as usize;
+                let nn = (op & 0xFF) as u8;
+                self.v_reg[x] = nn;
+            },
+
+            // 7XNN - VX += NN
+            // Adds given value to the VX reigster
+            (7,_,_,_) => {
+                let x = digit2 as usize;
+                let nn = (op & 0xFF) as u8;
+                // use wrapping_add to avoid rust panics at overflows.
+                // Either way VF is left alone, 7XNN has no carry
+                if self.config.add_immediate_saturates {
+                    self.v_reg[x] = self.v_reg[x].saturating_add(nn);
+                } else {
+                    self.v_reg[x] = self.v_reg[x].wrapping_add(nn); 
+                }
+            },
+
+            // 8XY0 - VX = VY
+            // Set the VX register to the value of VY
+            (8,_,_,0) => {
+                let x = digit2 as usize;
+                let y = digit3 as usize;
+                self.v_reg[x] = self.v_reg[y];
+            },
+            
+            // 8XY1 - Bitwise OR operation (VX |= VY)
+            (8,_,_,1) => {
+                let x = digit2 as usize;
+                let y = digit3 as usize;
+                self.v_reg[x] |= self.v_reg[y];
+                if self.config.vf_reset {
+                    self.v_reg[0xF] = 0;
+                }
+            },
+            
+            // 8XY2 - Bitwise AND operation (VX &= VY)
+            (8,_,_,2) => {
+                let x = digit2 as usize;
+                let y = digit3 as usize;
+                self.v_reg[x] &= self.v_reg[y];
+                if self.config.vf_reset {
+                    self.v_reg[0xF] = 0;
+                }
+            },
+            
+            // 8XY3 - Bitwise XOR operation (VX ^= VY)
+            (8,_,_,3) => {
+                let x = digit2 as usize;
+                let y = digit3 as usize;
+                self.v_reg[x] ^= self.v_reg[y];
+                if self.config.vf_reset {
+                    self.v_reg[0xF] = 0;
+                }
+            },
+
+            // 8XY4 - VX += VY
+            // Does the operation then sets the Flag register to indicate whether
+            // or not an overflow occured (if overflow, flag =1, 0 if not)
+            (8,_,_,4) => {
+                let x = digit2 as usize;
+                let y = digit3 as usize;
+
+                let (new_vx, carry) = self.v_reg[x].overflowing_add(self.v_reg[y]);
+                let new_vf = if carry { 1 } else { 0 };
+
+                self.v_reg[x] = new_vx;
+                self.v_reg[0xF] = new_vf;
+            },
+
+            // 8XY5 - VX -= VY
+            // Same as the last one except subtraction instead of addtion.
+            (8,_,_,5) => {
+                let x = digit2 as usize;
+                let y = digit3 as usize;
+             
+                let(new_vx, borrow) = self.v_reg[x].overflowing_sub(self.v_reg[y]);
+                let new_vf = if borrow { 0 } else { 1 };
+
+                self.v_reg[x] = new_vx;
+                self.v_reg[0xF] = new_vf;
+            },
+
+            // 8XY6 - Single right shift of VX (VX >>= 1)
+            // bit that is dropped off is stored in the VF register.
+            // The flag is written last, so with X == F (8F06) VF ends up
+            // holding the shifted out bit rather than the shifted value
+            (8,_,_,6) => {
+                let x = digit2 as usize;
+                if self.config.shift_uses_vy {
+                    self.v_reg[x] = self.v_reg[digit3 as usize];
+                }
+                let lsb = self.v_reg[x] & 1;
+                self.v_reg[x] >>= 1;
+                self.v_reg[0xF] = lsb;
+            },
+
+            // 8XY7 - VX = VY - VX
+            // similar to 8XY5 but in opposite direction
+            (8,_,_,7) => {
+                let x = digit2 as usize;
+                let y = digit3 as usize;
+                
+                let (new_vx, borrow) = self.v_reg[y].overflowing_sub(self.v_reg[x]);
+                let new_vf = if borrow { 0 } else { 1 };
+
+                self.v_reg[x] = new_vx;
+                self.v_reg[0xF] = new_vf;
+            },
+
+            // 8XYE - Single left shift of VX (VX <<= 1)
+            // Store the overflowed value in the flag register, last for
+            // the same reason as 8XY6
+            (8,_,_,0xE) => {
+                let x = digit2 as usize;
+                if self.config.shift_uses_vy {
+                    self.v_reg[x] = self.v_reg[digit3 as usize];
+                }
+                let msb = (self.v_reg[x] >> 7) & 1;
+                self.v_reg[x] <<= 1;
+                self.v_reg[0xF] = msb;
+            },
+
+            // 9XY0 - Skip if VX != VY
+            // Same as 5XY0 but with an inequality
+            (9,_,_,0) => {
+                let x = digit2 as usize;
+                let y = digit3 as usize;
+                if self.v_reg[x] != self.v_reg[y] {
+                    self.skip_next();
+                }
+            },
+
+            // ANNN - I = NNN
+            (0xA,_,_,_) => {
+                self.i_reg = nnn(op);
+            },
+        
+            // BNNN - Jump to V0 + NNN
+            // Depending on the jump quirk this is BXNN instead, jumping to
+            // XNN + VX. See JumpQuirk for the details
+            (0xB,_,_,_) => {
+                let nnn = nnn(op) as usize;
+                match self.config.jump_quirk {
+                    JumpQuirk::V0 => {
+                        self.set_pc(self.v_reg[0] as usize + nnn);
+                    },
+                    JumpQuirk::Chip48 => {
+                        let vx = self.v_reg[digit2 as usize] as usize;
+                        self.set_pc((vx + nnn) & 0xFFF);
+                    },
+                    JumpQuirk::SuperChip => {
+                        let vx = self.v_reg[digit2 as usize] as usize;
+                        self.set_pc(vx + nnn);
+                    },
+                }
+            },
+
+            // CXNN - VC = rand() & NN
+            // Random number generator, which is AND'd which is then
+            // AND'd with the lower 8-bits of the opcode
+            (0xC,_,_,_) => {
+                let x = digit2 as usize;
+                let nn = (op & 0xFF) as u8;
+                if let Some(undo) = self.undo.as_mut() {
+                    undo.rng = Some(self.rng.clone());
+                }
+                let rng: u8 = self.rng.gen(); // u8 so gen() knows what to generate
+                self.v_reg[x] = rng & nn;
+            },
+
+            // DXYN - Draw Sprite
+            // On SCHIP and up, DXY0 draws a 16x16 sprite (2 bytes per row)
+            (0xD,_,_,_) => {
+                let (width, height) = self.screen_dims();
+
+                // Get the (x, y) coords for our sprite. The starting point
+                // always wraps onto the screen, even when clipping
+                let x_coord = self.v_reg[digit2 as usize] as usize % width;
+                let y_coord = self.v_reg[digit3 as usize] as usize % height;
+                
+                // The last digit determines how many rows high our sprite is 
+                let (num_rows, num_cols) = if digit4 == 0 && self.schip_enabled() {
+                    (16, 16)
+                } else {
+                    (digit4 as usize, 8)
+                };
+                let bytes_per_row = num_cols / 8;
+
+                // Keep track if any pixels were flipped, and where
+                let mut flipped = false;
+                let mut first_collision = None;
+                self.draw_collisions = 0;
+                // and how many lit pixels got clipped off
+                let mut clipped = 0;
+
+                // Draw into each selected bitplane in turn. Only XO-CHIP
+                // has more than the one, and there the sprite for the second
+                // plane comes straight after the first plane's
+                let sprite_len = num_rows * bytes_per_row * self.selected_plane_indices().count();
+                if self.sprite_overruns(sprite_len) {
+                    self.emit(DebugEvent::SpriteOverrun { addr: self.i_reg, len: sprite_len });
+                }
+
+                let mut sprite_addr = self.i_reg as usize;
+                for plane in self.selected_plane_indices() {
+                    // iterate over each row of our sprite
+                    for y_line in 0..num_rows {
+                        // Determine which memory address our row's data is stored
+                        // and pack the whole row into one value
+                        let addr = sprite_addr + y_line * bytes_per_row;
+                        let mut pixels: u16 = 0;
+                        for byte in 0..bytes_per_row {
+                            pixels = (pixels << 8) | self.ram[self.ram_index(addr + byte)] as u16;
+                        }
+
+                        let mut y = y_coord + y_line;
+                        if y >= height {
+                            if self.config.clip_sprites {
+                                clipped += pixels.count_ones();
+                                continue;
+                            }
+                            y %= height;
+                        }
+
+                        // Iterate over each column in our row
+                        for x_line in 0..num_cols { 
+                            // Use a mask to fetch current pixel's bit. Only flip if a 1
+                            if sprite_pixel(pixels, num_cols, x_line) {
+                                // Sprites wrap around the screen unless clipped
+                                let mut x = x_coord + x_line;
+                                if x >= width {
+                                    if self.config.clip_sprites {
+                                        clipped += 1;
+                                        continue;
+                                    }
+                                    x %= width;
+                                }
+
+                                // Get our pixel's index for our 1D screen array.
+                                // x and y were wrapped or clipped above, so this
+                                // can only fail if the screen got out of sync
+                                // with the resolution
+                                let idx = match self.pixel_index(x, y) {
+                                    Some(idx) => idx,
+                                    None => continue,
+                                };
+                                // Check if we're about to flip the pixel and set
+                                let lit = self.plane_mut(plane)[idx];
+                                if lit {
+                                    if !flipped {
+                                        first_collision = Some((x, y));
+                                    }
+                                    self.draw_collisions += 1;
+                                }
+                                flipped |= lit;
+                                self.flip_pixel(plane, idx);
+                                self.mark_dirty();
+                            }
+                        }
+                    }
+                    sprite_addr += num_rows * bytes_per_row;
+                }
+                self.drew = true;
+                if let Some((x, y)) = first_collision {
+                    self.emit(DebugEvent::Collision { x, y });
+                }
+                if clipped > 0 {
+                    self.emit(DebugEvent::Clipped { pixels: clipped });
+                }
+                if flipped {
+                    self.v_reg[0xF] = 1;
+                } else if !self.config.dxyn_preserve_vf_on_no_collision {
+                    self.v_reg[0xF] = 0;
+                }
+
+                if self.config.display_wait {
+                    self.waiting_for_vblank = true;
+                }
+            },
+
+            // EX9E - Skip if Key Pressed
+            // Skips to next instruction if the index stored in VX is pressed
+            (0xE,_,9,0xE) => {
+               let x = digit2 as usize;
+               let vx = self.v_reg[x];
+               let key = self.key_down(vx);
+               if key {
+                   self.skip_next();
+               }
+            },
+
+            // EXA1 - Skip if key not pressed
+            (0xE,_,0xA,1) => {
+                let x = digit2 as usize;
+                let vx = self.v_reg[x];
+                let key = self.key_down(vx);
+                if !key {
+                    self.skip_next();
+                }
+            },
+
+            // F000 NNNN - I = NNNN (XO-CHIP)
+            // The only 4 byte instruction. The full 16-bit address is the
+            // next two bytes after the opcode, which get skipped over
+            (0xF,0,0,0) => {
+                self.i_reg = self.read_u16(self.pc as usize);
+                self.advance_pc();
+            },
+
+            // FN01 - Select bitplanes N (XO-CHIP)
+            // N is a bitmask, 1 is plane 0, 2 is plane 1 and 3 both
+            (0xF,_,0,1) => {
+                self.selected_planes = digit2 as u8 & 0x3;
+            },
+
+            // FX07 - VX = DT
+            // Stores the timer value into one of of the V registers
+            (0xF,_,0,7) => {
+                let x = digit2 as usize;
+                self.v_reg[x] = self.dt;
+            },
+
+            // FX0A - Wait for key press (blocking)
+            // If nothing is held down yet we stop and wait, checking the
+            // keys again every cycle until one is
+            (0xF,_,0,0xA) => {
+                let x = digit2 as usize;
+                match self.first_pressed_key() {
+                    Some(key) => self.v_reg[x] = key,
+                    None => self.waiting_for_key = Some(x),
+                }
+            },
+
+            // FX15 - DT = VX
+            // Resets the delay timer to a value from V register
+            (0xF,_,1,5) => {
+                let x = digit2 as usize;
+                self.dt = self.v_reg[x];
+            },
+
+
+            // FX18 - ST = VX
+            // Store value from V register into Sound Timer
+            (0xF,_,1,8) => {
+                let x = digit2 as usize;
+                let was_silent = self.st == 0;
+                self.st = self.v_reg[x];
+                self.sound_started = was_silent && self.st > 0;
+                if self.sound_started && !self.beeps_muted {
+                    let frames = self.st;
+                    if let Some(cb) = self.sound_start_callback.as_mut() {
+                        cb(frames);
+                    }
+                }
+            },
+
+            // FX1E - I += VX
+            // Takes value stored in VX and adds it to I register
+            // Rolls over to zero if overflow, unless configured to saturate
+            // at the top of RAM
+            (0xF,_,1,0xE) => {
+                let x = digit2 as usize;
+                let vx = self.v_reg[x] as u16;
+                if self.config.i_saturates {
+                    let top = (self.ram.len() - 1) as u16;
+                    self.i_reg = self.i_reg.saturating_add(vx).min(top);
+                } else {
+                    self.i_reg = self.i_reg.wrapping_add(vx);
+                }
+            },
+
+            // FX29 - Set I to Font Address
+            // Note that since we are storing fonts at the start of RAM
+            // and they take up 5 bytes each, their location is simply their
+            // value multiplied by 5
+            // Only 0-F have a glyph, so just the low nibble of VX is used.
+            // Otherwise VX=0x2A would point 210 bytes into RAM, well past
+            // the end of the font
+            (0xF,_,2,9) => {
+                let x = digit2 as usize;
+                let c = (self.v_reg[x] & 0xF) as u16;
+                self.i_reg = c *5;
+            },
+
+            // FX30 - Set I to Big Font Address (SCHIP)
+            // Same idea as FX29, but the big font glyphs are 10 bytes each
+            // and come right after the small font
+            (0xF,_,3,0) => {
+                let x = digit2 as usize;
+                let c = (self.v_reg[x] & 0xF) as u16;
+                self.i_reg = self.big_font_addr() + c * 10;
+            },
+
+            // FX33 - I = BCD of VX
+            // Store the binary coded decimal of a number in the VX register
+            // into the I register
+            (0xF,_,3,3) => {
+                let x = digit2 as usize;
+                let vx = self.v_reg[x] as f32;
+
+                // Fetch the hundreds digit by dividing by 100 and tossing the decimal
+                let hundreds = (vx / 100.0).floor() as u8;
+                // Fetch the tens digit by dividing by 10, toss ones digit and decimal
+                let tens = ((vx / 10.0) % 10.0).floor() as u8;
+                // Fetch the ones digit by tossing the hundreds and the tens
+                let ones = (vx % 10.0) as u8;
+                
+                let i = self.i_reg as usize;
+                self.write_ram(i, hundreds);
+                self.write_ram(i + 1, tens);
+                self.write_ram(i + 2, ones);
+            },
+
+            // FX55 - Store V0 through VX into I
+            (0xF,_,5,5) => {
+                let x = digit2 as usize;
+                let i = self.i_reg as usize;
+                for idx in 0..=x {
+                    self.write_ram(i + idx, self.v_reg[idx]);
+                }
+                if self.config.load_store_increments_i {
+                    self.i_reg = self.i_reg.wrapping_add(x as u16 + 1);
+                }
+            },
+
+            // FX65 - Load I into V0 through VX
+            (0xF,_,6,5) => {
+                let x = digit2 as usize;
+                let i = self.i_reg as usize;
+                for idx in 0..=x {
+                    self.v_reg[idx] = self.ram[self.ram_index(i + idx)]
+                }
+                if self.config.load_store_increments_i {
+                    self.i_reg = self.i_reg.wrapping_add(x as u16 + 1);
+                }
+            },
+
+            // FX75 - Store V0 through VX into the RPL flags (SCHIP)
+            // These survive a reset, like the HP48's user flags did
+            (0xF,_,7,5) => {
+                let x = digit2 as usize;
+                self.rpl_flags[..=x].copy_from_slice(&self.v_reg[..=x]);
+            },
+
+            // FX85 - Load the RPL flags into V0 through VX (SCHIP)
+            (0xF,_,8,5) => {
+                let x = digit2 as usize;
+                self.v_reg[..=x].copy_from_slice(&self.rpl_flags[..=x]);
+            },
+
+            // Everything else. Usually a corrupt ROM or the PC running off
+            // into data, so report it rather than panic
+            (_,_,_,_) => {
+                if !self.run_custom_opcode(op) {
+                    return Err(Chip8Error::UnknownOpcode { op });
+                }
+            },
+        };
+        Ok(())
+    }
+
+
+
+} // END Emu impl
+
+impl Default for Emu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for Emu {
+    fn drop(&mut self) {
+        // Don't leave the audio device droning on if we're dropped mid-beep.
+        // The beep callback gets its final `false` before any of our fields
+        // (the callback included) are dropped
+        self.set_beeping(false);
+    }
+}
+
+
+pub(crate) fn be_u16(bytes: &[u8]) -> u16 {
+    // The 16-bit value in the first two bytes. CHIP-8 is big endian all
+    // the way through, opcodes and F000's address alike, so 12 34 is
+    // 0x1234 and never 0x3412
+    u16::from_be_bytes([bytes[0], bytes[1]])
+}
+
+pub(crate) fn nnn(op: u16) -> u16 {
+    // The 12-bit address in the low three nibbles of 1NNN, 2NNN, ANNN and
+    // BNNN. Always 0x000-0xFFF, even with more RAM than that
+    op & 0x0FFF
+}
+
+pub(crate) fn sprite_pixel(row: u16, width: usize, col: usize) -> bool {
+    // Whether pixel `col` of one sprite row is lit. Sprite rows are `width`
+    // bits wide (8, or 16 for SCHIP's big sprites) with the leftmost pixel
+    // in the most significant bit
+    (row & (1 << (width - 1 - col))) != 0
+}
+
+
+// Roughly how many machine cycles the COSMAC VIP gets through in one 60Hz frame
+pub const VIP_CYCLES_PER_FRAME: u32 = 3668;
+
+// Extra machine cycles per sprite byte a DXYN draws, see vip_draw_timing
+const VIP_DRAW_BYTE_COST: u32 = 48;
+
+pub fn cycle_cost(op: u16) -> u32 {
+    // Approximate cost of an instruction in machine cycles, modelled on the
+    // original COSMAC VIP interpreter. These are relative weights for pacing
+    // run_frame, not an exact reproduction of the 1802 timings. Nothing
+    // is free, or run_frame could spin through RAM without ever using up
+    // its budget
+    let digit1 = (op & 0xF000) >> 12;
+    let digit2 = (op & 0x0F00) >> 8;
+    let digit3 = (op & 0x00F0) >> 4;
+    let digit4 = op & 0x000F;
+
+    match (digit1, digit2, digit3, digit4) {
+        (0,0,0xE,0) => 24,
+        (0,0,0xE,0xE) => 10,
+        // The SCHIP and XO-CHIP display ops go over the whole screen, same
+        // as 00E0. Anything else still costs the fetch
+        (0,0,0xC,_) | (0,0,0xD,_) | (0,0,0xF,_) => 24,
+        (0,_,_,_) => 6,
+        (1,_,_,_) => 12,
+        (2,_,_,_) => 26,
+        (3,_,_,_) | (4,_,_,_) => 10,
+        (5,_,_,_) | (9,_,_,_) => 18,
+        (6,_,_,_) => 6,
+        (7,_,_,_) => 10,
+        (8,_,_,_) => 44,
+        (0xA,_,_,_) => 12,
+        (0xB,_,_,_) => 22,
+        (0xC,_,_,_) => 36,
+        // Drawing is by far the slowest thing the interpreter does
+        (0xD,_,_,_) => 68,
+        (0xE,_,_,_) => 14,
+        (0xF,_,3,3) => 80,
+        // Register save/load cost grows with the number of registers
+        (0xF,_,5,5) | (0xF,_,6,5) => 14 + 14 * (digit2 as u32 + 1),
+        // The rest of the FXNN timer, key and I register ops
+        (_,_,_,_) => 16,
+    }
+}
+
+pub fn capabilities() -> Vec<&'static str> {
+    // The optional cargo features this build was compiled with, by their
+    // Cargo.toml names, so a frontend can show what it supports
+    let mut features = Vec::new();
+    if cfg!(feature = "schip") {
+        features.push("schip");
+    }
+    if cfg!(feature = "xo-chip") {
+        features.push("xo-chip");
+    }
+    if cfg!(feature = "octo") {
+        features.push("octo");
+    }
+    features
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{cell::RefCell, rc::Rc};
+
+    // Loads `code` into a fresh emulator with `config` and runs one tick
+    // per instruction, which is all most of these tests need
+    fn run(config: EmuConfig, code: &[[u8; 2]]) -> Emu {
+        let mut emu = Emu::with_config(config);
+        emu.load_rom(&program(code)).unwrap();
+        for _ in 0..code.len() {
+            emu.tick().unwrap();
+        }
+        emu
+    }
+
+    // Collects every DebugEvent the trace callback sees from here on
+    fn record_events(emu: &mut Emu) -> Rc<RefCell<Vec<DebugEvent>>> {
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let sink = events.clone();
+        emu.set_trace_callback(Box::new(move |event| sink.borrow_mut().push(event)));
+        events
+    }
+
+    #[test]
+    fn generated_program_runs_to_expected_state() {
+        let emu = run(EmuConfig::default(), &[
+            asm_set_reg(0, 5),
+            asm_add_reg(0, 1),
+            asm_skip_eq(0, 6),
+            asm_set_reg(1, 9),
+            asm_set_i(0x123),
+        ]);
+        assert_eq!((emu.v_reg[0], emu.v_reg[1], emu.i_reg), (6, 0, 0x123));
+        assert_eq!(asm_jump(0x20A), [0x12, 0x0A]);
+        assert_eq!(asm_draw(1, 2, 5), [0xD1, 0x25]);
+    }
+
+    #[test]
+    fn keys_bitmask_packs_key_states() {
+        let mut emu = Emu::new();
+        emu.keypress(0, true);
+        emu.keypress(15, true);
+        assert_eq!(emu.keys_bitmask(), 0x8001);
+        emu.set_keys_bitmask(0x0003);
+        assert_eq!(emu.keys_bitmask(), 0x0003);
+        assert!(emu.keys[1] && !emu.keys[15]);
+    }
+
+    #[test]
+    fn extract_strings_finds_embedded_title() {
+        let rom = [0x12, 0x00, b'P', b'O', b'N', b'G', 0x00, b'a', 0xFF, b'X', b'Y', b'Z'];
+        assert_eq!(extract_strings(&rom, 3), vec!["PONG".to_string(), "XYZ".to_string()]);
+        assert_eq!(extract_strings(&rom, 4), vec!["PONG".to_string()]);
+    }
+
+    #[test]
+    fn tick_synced_keeps_emulators_in_lockstep() {
+        // Random numbers into V0 and V1, and V2 counts presses of key 1
+        let rom = program(&[[0xC0, 0xFF], [0xC1, 0xFF], asm_set_reg(3, 1), [0xE3, 0x9E], asm_add_reg(2, 1), asm_jump(0x200)]);
+        let mut a = Emu::new();
+        let mut b = Emu::new();
+        a.seed_rng(7);
+        b.seed_rng(7);
+        a.load_rom(&rom).unwrap();
+        b.load_rom(&rom).unwrap();
+        for frame in 0..60u16 {
+            let remote_keys = if frame % 3 == 0 { 0x0002 } else { 0 };
+            a.tick_synced(remote_keys).unwrap();
+            b.tick_synced(remote_keys).unwrap();
+            assert_eq!(a.state_hash(), b.state_hash());
+        }
+        assert_eq!(a.keys_bitmask(), b.keys_bitmask());
+    }
+
+    #[test]
+    fn pressed_keys_lists_every_held_key() {
+        let mut emu = Emu::new();
+        emu.keypress(3, true);
+        emu.keypress(0xA, true);
+        emu.keypress(1, true);
+        assert_eq!(emu.pressed_keys(), vec![1, 3, 0xA]);
+        emu.keypress(3, false);
+        assert_eq!(emu.pressed_keys(), vec![1, 0xA]);
+    }
+
+    #[test]
+    fn extended_ram_is_addressable_past_0fff() {
+        // I = 0xFFF + 0x10, then store V0 and V1 there with FX55
+        let config = EmuConfig { ram_size: 0x10000, ..EmuConfig::default() };
+        let emu = run(config, &[
+            asm_set_i(0xFFF),
+            asm_set_reg(0, 0x10),
+            [0xF0, 0x1E],
+            asm_set_reg(0, 0x42),
+            asm_set_reg(1, 0x43),
+            [0xF1, 0x55],
+        ]);
+        assert_eq!(emu.ram().len(), 0x10000);
+        assert_eq!(emu.ram()[0x100F..0x1011], [0x42, 0x43]);
+        // With the default 4K a store that runs off the end wraps around
+        let emu = run(EmuConfig::default(), &[asm_set_i(0xFFF), asm_set_reg(0, 1), asm_set_reg(1, 2), [0xF1, 0x55]]);
+        assert_eq!((emu.ram()[0xFFF], emu.ram()[0]), (1, 2));
+    }
+
+    #[test]
+    fn try_with_config_rejects_bad_sizes() {
+        let bad_ram = EmuConfig { ram_size: 0x100, ..EmuConfig::default() };
+        assert_eq!(Emu::try_with_config(bad_ram).err(), Some(Chip8Error::BadRamSize { size: 0x100 }));
+        let too_big = EmuConfig { ram_size: 0x10001, ..EmuConfig::default() };
+        assert_eq!(Emu::try_with_config(too_big).err(), Some(Chip8Error::BadRamSize { size: 0x10001 }));
+        let bad_screen = EmuConfig { screen_width: 0, ..EmuConfig::default() };
+        assert_eq!(Emu::try_with_config(bad_screen).err(), Some(Chip8Error::BadScreenSize { width: 0, height: 32 }));
+        assert!(Emu::try_with_config(EmuConfig::default()).is_ok());
+    }
+
+    #[test]
+    fn validate_rom_lists_undecodable_opcodes() {
+        assert_eq!(validate_rom(&program(&[asm_set_reg(0, 1), asm_jump(0x200)])), Ok(()));
+        // 801F has no instruction, and the odd trailing byte is ignored
+        let rom = [0x60, 0x01, 0x80, 0x1F, 0x12, 0x00, 0x05];
+        assert_eq!(validate_rom(&rom), Err(vec![(0x202, 0x801F)]));
+    }
+
+    #[test]
+    fn validate_rom_copes_with_oversize_input() {
+        // More than fits in 64KB of RAM, so the addresses wrap past 0xFFFF
+        let unknown = validate_rom(&vec![0xFF; 0x10000]).unwrap_err();
+        assert_eq!(unknown.len(), 0x8000);
+        assert_eq!(unknown[0x7FFF], (0x01FE, 0xFFFF));
+    }
+
+    #[test]
+    fn pc_wraps_around_the_end_of_ram() {
+        // With 4K of RAM the instruction after 0xFFE is at 0x000, so a
+        // skip there lands on 0x002
+        let mut rom = vec![0; 0xE00];
+        rom[..2].copy_from_slice(&asm_jump(0xFFE));
+        rom[0xDFE..].copy_from_slice(&asm_skip_eq(0, 0));
+        let mut emu = Emu::from_rom(&rom).unwrap();
+        emu.tick().unwrap();
+        emu.tick().unwrap();
+        assert_eq!(emu.pc, 0x002);
+
+        rom[0xDFE..].copy_from_slice(&asm_skip_ne(0, 0));
+        let mut emu = Emu::from_rom(&rom).unwrap();
+        emu.tick().unwrap();
+        emu.tick().unwrap();
+        assert_eq!(emu.pc, 0x000);
+    }
+
+    #[cfg(feature = "octo")]
+    #[test]
+    fn import_octo_state_sets_registers() {
+        let mut emu = Emu::new();
+        emu.import_octo_state(r#"{ "pc": 516, "i": 291, "v": [5,0,0,0,0,0,0,0,0,0,0,0,0,0,0,9], "r": [768], "dt": 3, "hires": false, "name": "x\"y" }"#).unwrap();
+        assert_eq!(emu.cpu_state(), CpuState { pc: 0x204, i: 0x123, sp: 1, v: [5, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 9], dt: 3, st: 0 });
+        assert_eq!(emu.call_stack(), &[0x300]);
+
+        // Nothing changes when any field is bad
+        assert!(emu.import_octo_state(r#"{"i": 7, "pc": 70000}"#).is_err());
+        assert!(emu.import_octo_state(r#"{"v": [1,2]}"#).is_err());
+        assert_eq!(emu.i_reg, 0x123);
+    }
+
+    #[test]
+    fn loaded_rom_returns_the_loaded_bytes() {
+        let mut emu = Emu::new();
+        assert!(emu.loaded_rom().is_empty());
+        emu.load_rom(&[1, 2, 3]).unwrap();
+        assert_eq!(emu.loaded_rom(), &[1, 2, 3]);
+    }
+
+    #[test]
+    fn cycle_cost_weighs_draws_over_loads() {
+        assert!(cycle_cost(encode_draw(0, 1, 5)) > cycle_cost(encode_set_reg(0, 0x12)));
+        // run_frame stops once the budget is spent: ten loop jumps at 12
+        let mut emu = Emu::from_rom(&program(&[asm_jump(0x200)])).unwrap();
+        emu.run_frame(120).unwrap();
+        assert_eq!((emu.cycles(), emu.machine_cycles()), (10, 120));
+    }
+
+    #[test]
+    fn every_instruction_costs_cycles() {
+        for op in 0..=u16::MAX {
+            assert!(cycle_cost(op) > 0, "{:04X} is free", op);
+        }
+        // So a frame of 0000s ends at the budget instead of running all of RAM
+        let mut emu = Emu::new();
+        emu.run_frame(100).unwrap();
+        assert_eq!(emu.cycles(), 17);
+        assert_eq!(emu.pc, 0x200 + 17 * 2);
+    }
+
+    #[test]
+    fn run_frame_hands_fx0a_its_key() {
+        // F30A, then loop on the spot
+        let mut emu = Emu::from_rom(&[0xF3, 0x0A, 0x12, 0x02]).unwrap();
+        emu.run_frame(100).unwrap();
+        assert!(emu.is_waiting_for_key());
+        emu.run_frame(100).unwrap();
+        assert!(emu.is_waiting_for_key());
+        emu.keypress(5, true);
+        emu.run_frame(100).unwrap();
+        assert!(!emu.is_waiting_for_key());
+        assert_eq!(emu.v_reg[3], 5);
+        assert_eq!(emu.pc, 0x202);
+    }
+
+    #[test]
+    fn from_rom_loads_and_is_ready_to_tick() {
+        let mut emu = Emu::from_rom(&program(&[asm_set_reg(0, 1)])).unwrap();
+        assert_eq!(emu.ram()[0x200..0x202], [0x60, 0x01]);
+        emu.tick().unwrap();
+        assert_eq!(emu.v_reg[0], 1);
+        assert_eq!(Emu::from_rom(&[0; 0xE01]).err(), Some(Chip8Error::RomTooLarge { size: 0xE01, max: 0xE00 }));
+        assert!(Emu::from_rom(&[0; 0xE00]).is_ok());
+    }
+
+    #[test]
+    fn paused_timers_hold_still_while_ticking() {
+        let mut emu = run(EmuConfig::default(), &[asm_set_reg(0, 5), [0xF0, 0x15]]);
+        emu.set_timers_paused(true);
+        for _ in 0..3 {
+            emu.tick().unwrap();
+            emu.tick_timers();
+        }
+        assert_eq!(emu.dt, 5);
+        emu.set_timers_paused(false);
+        emu.tick_timers();
+        assert_eq!(emu.dt, 4);
+    }
+
+    #[test]
+    fn drawing_shows_up_only_after_present() {
+        let mut emu = run(EmuConfig::default(), &[asm_set_i(0), asm_draw(0, 0, 5)]);
+        assert!(emu.get_display().iter().all(|&lit| !lit));
+        emu.present();
+        assert!(emu.get_display()[0]);
+    }
+
+    #[test]
+    fn fx1e_saturates_at_top_of_ram() {
+        let code = [asm_set_i(0xFF0), asm_set_reg(0, 0xFF), [0xF0, 0x1E]];
+        let emu = run(EmuConfig { i_saturates: true, ..EmuConfig::default() }, &code);
+        assert_eq!(emu.i_reg, 0xFFF);
+        let emu = run(EmuConfig::default(), &code);
+        assert_eq!(emu.i_reg, 0x10EF);
+    }
+
+    #[test]
+    fn beep_callback_fires_once_per_edge() {
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let sink = events.clone();
+        let mut emu = Emu::from_rom(&program(&[asm_set_reg(0, 5), [0xF0, 0x18], asm_jump(0x204)])).unwrap();
+        emu.set_beep_callback(Box::new(move |beeping| sink.borrow_mut().push(beeping)));
+        emu.tick().unwrap();
+        emu.tick().unwrap();
+        for _ in 0..10 {
+            emu.tick().unwrap();
+            emu.tick_timers();
+        }
+        assert_eq!(*events.borrow(), vec![true, false]);
+    }
+
+    #[test]
+    fn ram_write_callback_sees_fx33_writes() {
+        let writes = Rc::new(RefCell::new(Vec::new()));
+        let sink = writes.clone();
+        let mut emu = Emu::from_rom(&program(&[asm_set_reg(0, 254), asm_set_i(0x300), [0xF0, 0x33]])).unwrap();
+        emu.set_ram_write_callback(Box::new(move |addr, value| sink.borrow_mut().push((addr, value))));
+        for _ in 0..3 {
+            emu.tick().unwrap();
+        }
+        assert_eq!(*writes.borrow(), vec![(0x300, 2), (0x301, 5), (0x302, 4)]);
+    }
+
+    #[cfg(feature = "schip")]
+    #[test]
+    fn superchip_platform_enables_hires() {
+        let emu = run(EmuConfig::for_platform(Platform::SuperChip), &[[0x00, 0xFF]]);
+        assert!(emu.hires);
+        let mut emu = Emu::for_platform(Platform::Chip8);
+        emu.load_rom(&[0x00, 0xFF]).unwrap();
+        assert!(emu.tick().is_err());
+        assert!(!emu.hires);
+    }
+
+    #[cfg(feature = "schip")]
+    #[test]
+    fn superchip_big_sprite_and_scroll() {
+        // A 16x16 sprite from the big font at the top left, then scroll
+        // down 2 rows
+        let mut emu = Emu::for_platform(Platform::SuperChip);
+        emu.load_rom(&program(&[asm_set_reg(0, 0), [0xF0, 0x30], asm_draw(0, 0, 0), [0x00, 0xC2]])).unwrap();
+        for _ in 0..3 {
+            emu.tick().unwrap();
+        }
+        emu.present();
+        let before = emu.get_display().to_vec();
+        let lit = emu.ram()[80..112].iter().map(|b| b.count_ones() as usize).sum::<usize>();
+        assert_eq!(before.iter().filter(|&&lit| lit).count(), lit);
+
+        emu.tick().unwrap();
+        emu.present();
+        let after = emu.get_display();
+        assert!(after[..2 * 64].iter().all(|&lit| !lit));
+        assert_eq!(after[2 * 64..], before[..30 * 64]);
+    }
+
+    #[test]
+    fn chip8_rejects_schip_opcodes() {
+        for op in [0x00FFu16, 0x00FE, 0x00FB, 0x00FC, 0x00FD, 0x00C4, 0xF130, 0xF175, 0xF185] {
+            let mut emu = Emu::for_platform(Platform::Chip8);
+            emu.load_rom(&op.to_be_bytes()).unwrap();
+            assert_eq!(emu.tick(), Err(Chip8Error::UnsupportedOpcode { op, platform: Platform::Chip8 }));
+        }
+    }
+
+    #[test]
+    fn sprite_bounds_is_tight_around_lit_pixels() {
+        assert_eq!(sprite_bounds(10, 5, &[0, 0, 0b0001_1000, 0b0010_0000, 0]), (12, 7, 14, 8));
+        assert_eq!(sprite_bounds(1, 2, &[0, 0]), (1, 2, 1, 2));
+        assert_eq!(sprite_bounds(250, 0, &[0xFF]), (250, 0, 255, 0));
+    }
+
+    #[test]
+    fn cycle_driven_timers_tick_every_n_cycles() {
+        let config = EmuConfig { cycle_driven_timers: true, cycles_per_timer_tick: 4, ..EmuConfig::default() };
+        let mut emu = run(config, &[asm_set_reg(0, 16), [0xF0, 0x15]]);
+        assert_eq!(emu.dt, 16);
+        for _ in 0..10 {
+            emu.tick().unwrap();
+        }
+        // 12 cycles in, the timers have ticked at cycles 4, 8 and 12
+        assert_eq!(emu.dt, 13);
+    }
+
+    #[test]
+    fn ram_at_i_reflects_writes() {
+        let mut emu = run(EmuConfig::default(), &[asm_set_i(0x300)]);
+        emu.write_at_i(&[0xAA, 0x55]).unwrap();
+        assert_eq!(emu.ram_at_i(3), &[0xAA, 0x55, 0]);
+        // Both stop at the end of RAM
+        let mut emu = run(EmuConfig::default(), &[asm_set_i(0xFFE)]);
+        assert_eq!(emu.ram_at_i(10).len(), 2);
+        assert_eq!(emu.write_at_i(&[1, 2, 3]), Err(Chip8Error::OutOfBounds { addr: 0xFFE, len: 3 }));
+    }
+
+    #[test]
+    fn undo_last_reverts_one_tick() {
+        let mut emu = Emu::from_rom(&program(&[asm_set_reg(0, 5), asm_set_reg(0, 9)])).unwrap();
+        emu.tick().unwrap();
+        let before = emu.state_hash();
+        emu.tick().unwrap();
+        assert_ne!(emu.state_hash(), before);
+        assert!(emu.undo_last());
+        assert_eq!(emu.state_hash(), before);
+        assert!(!emu.undo_last());
+    }
+
+    #[test]
+    fn undo_last_reverts_ram_screen_and_counters() {
+        // Each instruction gets run after the same setup, then undone, and
+        // everything tick() touches has to be back as it was
+        let setup = [asm_set_reg(0, 0x12), asm_set_reg(1, 0x34), asm_set_i(0x300), asm_draw(0, 1, 5), [0xF1, 0x55]];
+        let steps = [[0xF1, 0x55], [0xF0, 0x33], asm_draw(1, 0, 5), asm_cls(), [0xC0, 0xFF], asm_call(0x400)];
+        for step in steps {
+            let mut code = setup.to_vec();
+            code.push(step);
+            let mut emu = Emu::from_rom(&program(&code)).unwrap();
+            for _ in 0..setup.len() {
+                emu.tick().unwrap();
+            }
+            let before = (emu.state_hash(), emu.opcode_histogram(), emu.pc_history(), emu.nop_run);
+            let mut rng = emu.rng.clone();
+            emu.tick().unwrap();
+            assert!(emu.undo_last());
+            assert_eq!((emu.state_hash(), emu.opcode_histogram(), emu.pc_history(), emu.nop_run), before, "{:02X?}", step);
+            assert_eq!(emu.rng.gen::<u64>(), rng.gen::<u64>());
+        }
+    }
+
+    #[test]
+    fn custom_opcode_handler_runs_for_unused_opcode() {
+        // 5XY1 is free on every platform, make it VX += VY
+        let mut emu = Emu::from_rom(&program(&[asm_set_reg(1, 2), asm_set_reg(2, 3), [0x51, 0x21]])).unwrap();
+        emu.register_opcode(0xF00F, 0x5001, |emu: &mut Emu, op| {
+            let (x, y) = (((op >> 8) & 0xF) as usize, ((op >> 4) & 0xF) as usize);
+            emu.v_reg[x] += emu.v_reg[y];
+        });
+        for _ in 0..3 {
+            emu.tick().unwrap();
+        }
+        assert_eq!((emu.v_reg[1], emu.pc), (5, 0x206));
+    }
+
+    #[test]
+    fn run_until_draw_stops_after_first_draw() {
+        let mut emu = Emu::from_rom(&program(&[asm_set_reg(0, 1), asm_set_reg(1, 2), asm_set_i(0), asm_draw(0, 1, 5), asm_jump(0x208)])).unwrap();
+        assert!(!emu.run_until_draw(3));
+        assert!(emu.run_until_draw(3));
+        assert_eq!(emu.cycles(), 4);
+        assert!(emu.is_dirty());
+        assert!(!emu.run_until_draw(100));
+        // An error ends the run like running out of cycles does
+        let mut emu = Emu::from_rom(&program(&[asm_ret(), asm_draw(0, 0, 1)])).unwrap();
+        assert!(!emu.run_until_draw(10));
+        assert_eq!(emu.cycles(), 0);
+    }
+
+    #[cfg(feature = "xo-chip")]
+    #[test]
+    fn xochip_5xy2_stores_register_range() {
+        // Saves V3 down to V1, then loads V0 up to V2 back from the same place
+        let emu = run(EmuConfig::for_platform(Platform::XoChip), &[
+            asm_set_reg(1, 0x22),
+            asm_set_reg(2, 0x33),
+            asm_set_reg(3, 0x44),
+            asm_set_i(0x300),
+            [0x53, 0x12],
+            [0x50, 0x23],
+        ]);
+        assert_eq!(emu.ram_at_i(3), &[0x44, 0x33, 0x22]);
+        assert_eq!(emu.v_reg[..3], [0x44, 0x33, 0x22]);
+        assert_eq!(emu.i_reg, 0x300);
+    }
+
+    #[test]
+    fn unknown_5xyn_and_9xyn_low_nibbles() {
+        let mut emu = Emu::from_rom(&[0x51, 0x22]).unwrap();
+        assert!(matches!(emu.tick(), Err(Chip8Error::UnsupportedOpcode { .. })));
+        for op in [0x5127, 0x9121] {
+            let mut emu = Emu::from_rom(&u16::to_be_bytes(op)).unwrap();
+            assert_eq!(emu.tick(), Err(Chip8Error::UnknownOpcode { op }));
+        }
+    }
+
+    #[test]
+    fn skip_9xy0_compares_vx_with_vy() {
+        let emu = run(EmuConfig::default(), &[asm_set_reg(0, 1), asm_set_reg(1, 2), [0x90, 0x10], asm_set_reg(2, 7)]);
+        assert_eq!((emu.v_reg[2], emu.pc), (0, 0x20A));
+        let emu = run(EmuConfig::default(), &[asm_set_reg(0, 1), asm_set_reg(1, 1), [0x90, 0x10], asm_set_reg(2, 7)]);
+        assert_eq!(emu.v_reg[2], 7);
+    }
+
+    #[test]
+    fn display_changed_tracks_the_last_tick_only() {
+        let mut emu = Emu::from_rom(&program(&[asm_set_i(0), asm_draw(0, 0, 5), asm_set_reg(0, 1)])).unwrap();
+        emu.tick().unwrap();
+        assert!(!emu.display_changed());
+        emu.tick().unwrap();
+        assert!(emu.display_changed());
+        emu.tick().unwrap();
+        assert!(!emu.display_changed());
+        assert!(emu.is_dirty());
+    }
+
+    #[test]
+    fn cpu_state_round_trips() {
+        let mut emu = run(EmuConfig::default(), &[asm_set_reg(0, 5), asm_set_i(0x123), asm_call(0x300)]);
+        let state = emu.cpu_state();
+        assert_eq!((state.pc, state.i, state.sp, state.v[0]), (0x300, 0x123, 1, 5));
+        emu.restore_cpu_state(CpuState { v: [9; 16], ..state });
+        emu.restore_cpu_state(state);
+        assert_eq!(emu.cpu_state(), state);
+    }
+
+    #[test]
+    fn detects_display_wait_flags_draw_loops() {
+        // 202: draw, add, jump back to 202
+        assert!(detects_display_wait(&program(&[asm_set_reg(0, 0), asm_draw(0, 1, 5), asm_add_reg(0, 1), asm_jump(0x202)])));
+        // the same loop without the draw in it
+        assert!(!detects_display_wait(&program(&[asm_set_reg(0, 0), asm_draw(0, 1, 5), asm_add_reg(0, 1), asm_jump(0x204)])));
+        assert!(!detects_display_wait(&program(&[asm_jump(0x200)])));
+        // Bigger than 64KB of RAM, and still no draw in any loop
+        assert!(!detects_display_wait(&[0x12, 0x00].repeat(0x8000)));
+    }
+
+    #[test]
+    fn collision_event_reports_first_overlap() {
+        // Font 0 at (0, 0), then again at (2, 1). Its top row (x = 2..5
+        // at y = 1) first hits the 0's side at (3, 1)
+        let mut emu = Emu::from_rom(&program(&[asm_set_i(0), asm_draw(0, 0, 5), asm_set_reg(1, 2), asm_set_reg(2, 1), asm_draw(1, 2, 5)])).unwrap();
+        let events = record_events(&mut emu);
+        for _ in 0..5 {
+            emu.tick().unwrap();
+        }
+        assert_eq!(*events.borrow(), vec![DebugEvent::Collision { x: 3, y: 1 }]);
+    }
+
+    #[test]
+    fn run_turbo_advances_cycles_and_timers() {
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let sink = events.clone();
+        let mut emu = Emu::from_rom(&program(&[asm_set_reg(0, 100), [0xF0, 0x15], [0xF0, 0x18], asm_jump(0x206)])).unwrap();
+        emu.set_beep_callback(Box::new(move |beeping| sink.borrow_mut().push(beeping)));
+        emu.run_turbo(5, 10).unwrap();
+        assert_eq!(emu.cycles(), 50);
+        assert_eq!((emu.dt, emu.st), (95, 95));
+        // One beep start for the whole run, not one per frame
+        assert_eq!(*events.borrow(), vec![true]);
+    }
+
+    #[test]
+    fn active_quirks_reflects_with_config() {
+        let config = EmuConfig { clip_sprites: true, ..EmuConfig::for_platform(Platform::XoChip) };
+        assert_eq!(Emu::with_config(config).active_quirks(), config);
+    }
+
+    #[test]
+    fn corner_draws_stay_in_bounds() {
+        for platform in [Platform::Chip8, Platform::SuperChip] {
+            for clip_sprites in [false, true] {
+                for hires in [false, true] {
+                    if hires && (platform == Platform::Chip8 || !cfg!(feature = "schip")) {
+                        continue;
+                    }
+                    let mut code = Vec::new();
+                    if hires {
+                        code.push([0x00, 0xFF]);
+                    }
+                    // 8x5 and 16x16 sprites at (255, 255)
+                    code.extend([asm_set_reg(0, 0xFF), asm_set_reg(1, 0xFF), asm_set_i(0), asm_draw(0, 1, 5), asm_draw(0, 1, 0)]);
+                    let mut emu = Emu::with_config(EmuConfig { clip_sprites, ..EmuConfig::for_platform(platform) });
+                    emu.load_rom(&program(&code)).unwrap();
+                    for _ in 0..30 {
+                        emu.tick().unwrap();
+                        emu.tick_timers();
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn reset_registers_keeps_ram_and_screen() {
+        let mut emu = run(EmuConfig::default(), &[asm_set_i(0), asm_draw(0, 0, 5), asm_set_reg(5, 9)]);
+        emu.present();
+        emu.reset_registers();
+        let state = emu.cpu_state();
+        assert_eq!((state.pc, state.i, state.sp, state.v), (0x200, 0, 0, [0; 16]));
+        emu.present();
+        assert!(emu.get_display()[0]);
+    }
+
+    #[test]
+    fn reset_registers_drops_a_pending_key_wait() {
+        let mut emu = Emu::from_rom(&program(&[[0xF0, 0x0A], asm_set_reg(0, 7)])).unwrap();
+        emu.tick().unwrap();
+        assert!(emu.is_waiting_for_key());
+        emu.reset_registers();
+        assert!(!emu.is_waiting_for_key());
+        emu.tick().unwrap();
+        assert!(emu.is_waiting_for_key());
+        assert_eq!(emu.pc, 0x202);
+    }
+
+    #[test]
+    fn sprite_grid_expands_font_rows() {
+        // The font's 1 starts 0x20, 0x60, 0x20 at address 5
+        let row = |bits: u8| (0..8).map(|col| bits & (0x80 >> col) != 0).collect::<Vec<bool>>();
+        assert_eq!(Emu::new().sprite_grid(5, 3), vec![row(0x20), row(0x60), row(0x20)]);
+    }
+
+    #[test]
+    fn new_with_fill_fills_ram_but_keeps_the_font() {
+        let mut emu = Emu::new_with_fill(0xAB);
+        assert_eq!(emu.v_reg, [0xAB; NUM_REGS]);
+        emu.load_rom(&program(&[asm_jump(0x200)])).unwrap();
+        emu.i_reg = 0x202;
+        assert_eq!(emu.ram_at_i(4), &[0xAB; 4]);
+        assert_eq!(emu.sprite_grid(0, 5), Emu::new().sprite_grid(0, 5));
+    }
+
+    #[test]
+    fn dropping_a_beeping_emu_stops_the_tone() {
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let sink = events.clone();
+        let mut emu = Emu::from_rom(&program(&[asm_set_reg(0, 5), [0xF0, 0x18]])).unwrap();
+        emu.set_beep_callback(Box::new(move |beeping| sink.borrow_mut().push(beeping)));
+        emu.tick().unwrap();
+        emu.tick().unwrap();
+        emu.tick_timers();
+        drop(emu);
+        assert_eq!(*events.borrow(), vec![true, false]);
+    }
+
+    #[test]
+    fn behaviorally_equal_spots_a_patched_byte() {
+        let rom_a = program(&[[0xC0, 0xFF], asm_add_reg(0, 1), asm_jump(0x200)]);
+        let mut rom_b = rom_a.clone();
+        assert!(behaviorally_equal(&rom_a, &rom_b, 50, 3));
+        rom_b[3] = 0x02;
+        assert!(!behaviorally_equal(&rom_a, &rom_b, 50, 3));
+    }
+
+    #[test]
+    fn jump_quirk_picks_the_bnnn_register() {
+        // The examples from JumpQuirk's comment, on 64KB of RAM
+        let bnnn = |jump_quirk, op: [u8; 2]| {
+            let code = [asm_set_reg(0, 0x10), asm_set_reg(2, 0x20), asm_set_reg(0xF, 0x02), op];
+            run(EmuConfig { jump_quirk, ram_size: MAX_RAM_SIZE, ..EmuConfig::default() }, &code).pc
+        };
+        assert_eq!((bnnn(JumpQuirk::V0, [0xB2, 0x34]), bnnn(JumpQuirk::V0, [0xBF, 0xFF])), (0x244, 0x100F));
+        assert_eq!((bnnn(JumpQuirk::Chip48, [0xB2, 0x34]), bnnn(JumpQuirk::Chip48, [0xBF, 0xFF])), (0x254, 0x001));
+        assert_eq!((bnnn(JumpQuirk::SuperChip, [0xB2, 0x34]), bnnn(JumpQuirk::SuperChip, [0xBF, 0xFF])), (0x254, 0x1001));
+        assert_eq!(EmuConfig::for_platform(Platform::SuperChip).jump_quirk, JumpQuirk::SuperChip);
+    }
+
+    #[test]
+    fn last_draw_collisions_counts_pixels_turned_off() {
+        // Font 0 (F0 90...), then the 0x60, 0x20 rows of font 1 over it:
+        // the first row turns off two of the 0's pixels, the second none
+        let emu = run(EmuConfig::default(), &[asm_set_i(0), asm_draw(0, 0, 5), asm_set_i(6), asm_draw(0, 0, 2)]);
+        assert_eq!(emu.last_draw_collisions(), 2);
+        assert_eq!(emu.v_reg[0xF], 1);
+    }
+
+    #[test]
+    fn disassemble_rom_lists_every_instruction() {
+        let listing = disassemble_rom(&[0x6A, 0x12, 0xD0, 0x15, 0x80, 0x1F, 0x12], 0x200);
+        assert_eq!(listing, vec![
+            (0x200, 0x6A12, "LD VA, 0x12".to_string()),
+            (0x202, 0xD015, "DRW V0, V1, 5".to_string()),
+            (0x204, 0x801F, "DW 0x801F".to_string()),
+            (0x206, 0x12, "DB 0x12".to_string()),
+        ]);
+        assert_eq!(disassemble(0x1234), "JP 0x234");
+        assert_eq!(disassemble(0x00E0), "CLS");
+    }
+
+    #[test]
+    fn cls_resets_vf_only_with_the_quirk() {
+        let code = [asm_set_reg(0xF, 1), [0x00, 0xE0]];
+        assert_eq!(run(EmuConfig::default(), &code).v_reg[0xF], 1);
+        assert_eq!(run(EmuConfig { cls_resets_vf: true, ..EmuConfig::default() }, &code).v_reg[0xF], 0);
+    }
+
+    #[test]
+    fn ram_shows_the_font_and_the_rom() {
+        let mut emu = Emu::from_rom(&program(&[asm_jump(0x234)])).unwrap();
+        assert_eq!(emu.ram()[0..5], [0xF0, 0x90, 0x90, 0x90, 0xF0]);
+        assert_eq!(emu.ram()[0x200..0x202], [0x12, 0x34]);
+        emu.ram_mut()[0x200] = 0x60;
+        assert_eq!(emu.ram()[0x200], 0x60);
+    }
+
+    #[test]
+    fn masked_off_keys_never_count_as_pressed() {
+        let code = [asm_set_reg(0, 0xF), [0xE0, 0x9E], asm_set_reg(1, 1)];
+        let mut emu = Emu::from_rom(&program(&code)).unwrap();
+        emu.keypress(0xF, true);
+        emu.set_valid_keys(0x7FFF);
+        for _ in 0..code.len() {
+            emu.tick().unwrap();
+        }
+        assert_eq!(emu.v_reg[1], 1);
+        // Without the mask the held key skips the load
+        let mut emu = Emu::from_rom(&program(&code)).unwrap();
+        emu.keypress(0xF, true);
+        for _ in 0..code.len() {
+            emu.tick().unwrap();
+        }
+        assert_eq!(emu.v_reg[1], 0);
+    }
+
+    #[test]
+    fn random_call_ret_sequences_never_panic() {
+        let mut rng = StdRng::seed_from_u64(7);
+        for _ in 0..200 {
+            let code: Vec<[u8; 2]> = (0..64)
+                .map(|_| if rng.gen_bool(0.5) { asm_call(0x200 + 2 * rng.gen_range(0, 64)) } else { asm_ret() })
+                .collect();
+            let mut emu = Emu::from_rom(&program(&code)).unwrap();
+            for _ in 0..500 {
+                match emu.tick() {
+                    Ok(()) => assert!(emu.call_stack().len() <= STACK_SIZE),
+                    Err(Chip8Error::StackOverflow { .. }) | Err(Chip8Error::StackUnderflow) => break,
+                    Err(other) => panic!("{}", other),
+                }
+            }
+        }
+        let mut emu = Emu::from_rom(&program(&[asm_ret()])).unwrap();
+        assert_eq!(emu.tick(), Err(Chip8Error::StackUnderflow));
+    }
+
+    #[test]
+    fn screen_hash_changes_with_one_pixel() {
+        let blank = Emu::new();
+        let mut emu = Emu::new();
+        emu.seed_rng(3);
+        assert_eq!(blank.screen_hash(), emu.screen_hash());
+        emu.load_rom(&program(&[asm_draw(0, 0, 1)])).unwrap();
+        emu.tick().unwrap();
+        emu.present();
+        assert_ne!(blank.screen_hash(), emu.screen_hash());
+    }
+
+    #[cfg(feature = "xo-chip")]
+    #[test]
+    fn xochip_00dn_scrolls_up() {
+        for hires in [false, true] {
+            let mut code = Vec::new();
+            if hires {
+                code.push([0x00, 0xFF]);
+            }
+            // One row of font 0 at (3, 10), scrolled up 4
+            code.extend([asm_set_reg(0, 3), asm_set_reg(1, 10), asm_set_i(0), asm_draw(0, 1, 1), [0x00, 0xD4]]);
+            let mut emu = run(EmuConfig::for_platform(Platform::XoChip), &code);
+            emu.present();
+            let (width, height) = emu.display_size();
+            let lit: Vec<usize> = (0..width * height).filter(|&idx| emu.get_display()[idx]).collect();
+            assert_eq!(lit, (0..4).map(|col| 6 * width + 3 + col).collect::<Vec<_>>());
+        }
+        let mut emu = Emu::from_rom(&program(&[[0x00, 0xD1]])).unwrap();
+        assert!(emu.tick().is_err());
+    }
+
+    #[test]
+    fn implemented_opcodes_follows_the_features() {
+        let ops = implemented_opcodes();
+        assert!(ops.contains(&"DXYN") && ops.contains(&"00E0"));
+        assert_eq!(ops.contains(&"00FF"), cfg!(feature = "schip"));
+        assert_eq!(ops.contains(&"00DN"), cfg!(feature = "xo-chip"));
+    }
+
+    #[test]
+    fn run_scripted_is_reproducible() {
+        let rom = program(&[[0xC0, 0xFF], asm_set_reg(1, 0), [0xE1, 0x9E], asm_add_reg(2, 1), asm_jump(0x200)]);
+        let script = [(10, 0), (10, 1), (5, 0)];
+        let hashes = || {
+            let mut emu = Emu::from_rom(&rom).unwrap();
+            emu.seed_rng(42);
+            emu.run_scripted(&script)
+        };
+        let first = hashes();
+        assert_eq!(first.len(), 3);
+        assert_eq!(first, hashes());
+        let mut emu = Emu::from_rom(&program(&[asm_ret()])).unwrap();
+        assert!(emu.run_scripted(&script).is_empty());
+    }
+
+    #[test]
+    fn fx29_uses_the_low_nibble_of_vx() {
+        let emu = run(EmuConfig::default(), &[asm_set_reg(3, 0x2A), [0xF3, 0x29]]);
+        assert_eq!(emu.i_reg, 0xA * 5);
+    }
+
+    #[test]
+    fn cycles_to_idle_counts_until_a_self_jump() {
+        // Counts V0 up to 5, then jumps to itself
+        let rom = program(&[asm_add_reg(0, 1), asm_skip_eq(0, 5), asm_jump(0x200), asm_jump(0x206)]);
+        let mut emu = Emu::from_rom(&rom).unwrap();
+        assert_eq!(emu.cycles_to_idle(100), Some(14));
+        assert!(emu.is_idle());
+        let mut emu = Emu::from_rom(&rom).unwrap();
+        assert_eq!(emu.cycles_to_idle(13), None);
+    }
+
+    #[test]
+    fn is_idle_ignores_1nnn_past_0fff() {
+        // 1200 at 0x1200 jumps back to 0x200, not to itself
+        let mut emu = Emu::with_config(EmuConfig { ram_size: MAX_RAM_SIZE, ..EmuConfig::default() });
+        emu.ram_mut()[0x1200..0x1202].copy_from_slice(&asm_jump(0x200));
+        emu.pc = 0x1200;
+        assert!(!emu.is_idle());
+        emu.pc = 0x200;
+        emu.ram_mut()[0x200..0x202].copy_from_slice(&asm_jump(0x200));
+        assert!(emu.is_idle());
+    }
+
+    #[test]
+    fn reserved_8xyn_nibbles_are_unknown_opcodes() {
+        for low in [0x8, 0x9, 0xA, 0xB, 0xC, 0xD, 0xF] {
+            let mut emu = Emu::from_rom(&[0x81, 0x20 | low]).unwrap();
+            assert_eq!(emu.tick(), Err(Chip8Error::UnknownOpcode { op: 0x8120 | low as u16 }));
+        }
+    }
+
+    #[test]
+    fn instruction_iter_reads_ahead_from_pc() {
+        let mut emu = Emu::from_rom(&program(&[asm_jump(0x204), asm_set_reg(0, 1), asm_set_reg(1, 2), asm_cls()])).unwrap();
+        emu.tick().unwrap();
+        let next: Vec<_> = emu.instruction_iter().take(3).collect();
+        assert_eq!(next, vec![(0x204, 0x6102), (0x206, 0x00E0), (0x208, 0)]);
+        assert_eq!(emu.instruction_iter().last(), Some((0xFFE, 0)));
+        assert_eq!(emu.instruction_iter().count(), (0x1000 - 0x204) / 2);
+    }
+
+    #[test]
+    fn jumping_into_a_data_region_fires_the_trap() {
+        let mut emu = Emu::from_rom(&program(&[asm_set_reg(0, 1), asm_jump(0x206), [0xF0, 0x90], [0x90, 0xF0]])).unwrap();
+        let events = record_events(&mut emu);
+        emu.mark_data_region(0x204, 0x208);
+        emu.tick().unwrap();
+        emu.tick().unwrap();
+        assert!(events.borrow().is_empty());
+        emu.tick().unwrap();
+        assert_eq!(*events.borrow(), vec![DebugEvent::DataExecuted { addr: 0x206 }]);
+    }
+
+    #[test]
+    fn clipped_sprites_report_the_hidden_pixels() {
+        // Font 0 has 14 lit pixels. At x = 62 only its two left columns
+        // show (7 pixels), at y = 30 only its top two rows (6 pixels)
+        for (x, y, clipped) in [(62, 0, 7), (0, 30, 8)] {
+            let mut emu = Emu::with_config(EmuConfig { clip_sprites: true, ..EmuConfig::default() });
+            let events = record_events(&mut emu);
+            emu.load_rom(&program(&[asm_set_reg(0, x), asm_set_reg(1, y), asm_set_i(0), asm_draw(0, 1, 5)])).unwrap();
+            for _ in 0..4 {
+                emu.tick().unwrap();
+            }
+            assert_eq!(*events.borrow(), vec![DebugEvent::Clipped { pixels: clipped }]);
+        }
+    }
+
+    #[test]
+    fn swap_rom_keeps_registers_and_pc() {
+        let mut emu = run(EmuConfig::default(), &[asm_set_reg(0, 7), asm_set_reg(1, 8), asm_jump(0x204)]);
+        emu.swap_rom(&program(&[asm_set_reg(2, 9)])).unwrap();
+        assert_eq!(emu.ram()[0x200..0x206], [0x62, 0x09, 0, 0, 0, 0]);
+        assert_eq!(emu.loaded_rom(), &[0x62, 0x09]);
+        assert_eq!((emu.v_reg[0], emu.v_reg[1], emu.pc), (7, 8, 0x204));
+        assert!(emu.swap_rom(&[0; 5000]).is_err());
+        assert_eq!(emu.loaded_rom(), &[0x62, 0x09]);
+    }
+
+    #[test]
+    fn draw_cap_defers_extra_draws_to_the_next_frame() {
+        let rom = program(&[asm_draw(0, 0, 1), asm_draw(0, 0, 1), asm_set_reg(0, 1), asm_jump(0x206)]);
+        let mut emu = Emu::with_config(EmuConfig { max_draws_per_frame: Some(1), ..EmuConfig::default() });
+        emu.load_rom(&rom).unwrap();
+        emu.run_frame(1000).unwrap();
+        assert_eq!(emu.pc, 0x202);
+        assert!(emu.get_display()[0]);
+        emu.run_frame(1000).unwrap();
+        assert_eq!(emu.v_reg[0], 1);
+        assert!(!emu.get_display()[0]);
+        // Uncapped, both draws and the load fit in one frame
+        let mut emu = Emu::from_rom(&rom).unwrap();
+        emu.run_frame(1000).unwrap();
+        assert_eq!(emu.v_reg[0], 1);
+    }
+
+    #[test]
+    fn address_opcodes_take_12_bit_addresses() {
+        assert_eq!(nnn(0xAFFF), 0xFFF);
+        let emu = run(EmuConfig::default(), &[[0xAF, 0xFF], [0x1A, 0xBC]]);
+        assert_eq!((emu.i_reg, emu.pc), (0xFFF, 0xABC));
+        let emu = run(EmuConfig::default(), &[[0x2F, 0x00]]);
+        assert_eq!((emu.pc, emu.call_stack()), (0xF00, &[0x202][..]));
+        // V0 + 0xFFF wraps around 4KB of RAM
+        let emu = run(EmuConfig::default(), &[asm_set_reg(0, 1), [0xBF, 0xFF]]);
+        assert_eq!(emu.pc, 0);
+    }
+
+    #[test]
+    fn call_and_return_emit_matching_events() {
+        let mut emu = Emu::from_rom(&program(&[asm_call(0x204), asm_jump(0x202), asm_ret()])).unwrap();
+        let events = record_events(&mut emu);
+        for _ in 0..3 {
+            emu.tick().unwrap();
+        }
+        assert_eq!(*events.borrow(), vec![
+            DebugEvent::Call { target: 0x204, return_addr: 0x202 },
+            DebugEvent::Return { return_addr: 0x202 },
+        ]);
+    }
+
+    #[test]
+    fn custom_fontset_glyphs_get_drawn() {
+        let mut font = [0; FONTSET_SIZE];
+        // The 7's top row becomes two pixels, at x = 0 and x = 7
+        font[5 * 7] = 0x81;
+        let mut emu = Emu::new();
+        assert!(emu.set_fontset(&font[..FONTSET_SIZE - 1]).is_err());
+        emu.set_fontset(&font).unwrap();
+        emu.load_rom(&program(&[asm_set_reg(0, 7), [0xF0, 0x29], asm_set_reg(0, 0), asm_draw(0, 0, 5)])).unwrap();
+        for _ in 0..4 {
+            emu.tick().unwrap();
+        }
+        emu.present();
+        let lit: Vec<usize> = (0..SCREEN_WIDTH * SCREEN_HEIGHT).filter(|&idx| emu.get_display()[idx]).collect();
+        assert_eq!(lit, vec![0, 7]);
+    }
+
+    #[cfg(feature = "schip")]
+    #[test]
+    fn fx30_points_into_the_big_font() {
+        let emu = run(EmuConfig::for_platform(Platform::SuperChip), &[asm_set_reg(0, 3), [0xF0, 0x30]]);
+        assert_eq!(emu.big_font_addr(), 80);
+        assert_eq!(emu.i_reg, emu.big_font_addr() + 30);
+    }
+
+    #[test]
+    fn from_state_starts_from_the_given_state() {
+        let mut ram = vec![0; 4096];
+        ram[0x300..0x302].copy_from_slice(&asm_set_reg(1, 5));
+        let state = CpuState { pc: 0x300, i: 0x123, sp: 2, v: [9; 16], dt: 4, st: 5 };
+        let mut emu = Emu::from_state(state, &ram).unwrap();
+        assert_eq!(emu.cpu_state(), state);
+        assert_eq!(emu.ram(), &ram[..]);
+        emu.tick().unwrap();
+        assert_eq!(emu.v_reg[1], 5);
+        assert_eq!(Emu::from_state(state, &[0; 100]).err(), Some(Chip8Error::BadRamSize { size: 100 }));
+    }
+
+    #[test]
+    fn detect_platform_picks_the_newest_opcode() {
+        assert_eq!(detect_platform(&program(&[asm_cls(), [0x00, 0xFF], asm_jump(0x200)])), Platform::SuperChip);
+        assert_eq!(detect_platform(&program(&[asm_cls(), [0x51, 0x22], [0x00, 0xFF]])), Platform::XoChip);
+        assert_eq!(detect_platform(&program(&[asm_cls()])), Platform::Chip8);
+        assert_eq!(detect_platform(&[]), Platform::Chip8);
+    }
+
+    #[test]
+    fn add_immediate_saturates_with_the_quirk() {
+        let code = [asm_set_reg(2, 0xFF), asm_add_reg(2, 5)];
+        let emu = run(EmuConfig { add_immediate_saturates: true, ..EmuConfig::default() }, &code);
+        assert_eq!((emu.v_reg[2], emu.v_reg[0xF]), (0xFF, 0));
+        let emu = run(EmuConfig::default(), &code);
+        assert_eq!((emu.v_reg[2], emu.v_reg[0xF]), (0x04, 0));
+    }
+
+    #[test]
+    fn reset_timers_leaves_registers_alone() {
+        let mut emu = run(EmuConfig::default(), &[asm_set_reg(0, 9), [0xF0, 0x15], [0xF0, 0x18]]);
+        assert_eq!((emu.dt, emu.st), (9, 9));
+        emu.reset_timers();
+        assert_eq!((emu.dt, emu.st, emu.v_reg[0], emu.pc), (0, 0, 9, 0x206));
+    }
+
+    #[test]
+    fn pc_history_traces_back_to_a_crash() {
+        let code = [asm_set_reg(0, 1), asm_jump(0x206), asm_cls(), asm_call(0x208), [0x80, 0x08]];
+        let mut emu = Emu::from_rom(&program(&code)).unwrap();
+        for _ in 0..3 {
+            emu.tick().unwrap();
+        }
+        assert!(emu.tick().is_err());
+        assert_eq!(emu.pc_history(), vec![0x200, 0x202, 0x206, 0x208]);
+        let emu = run(EmuConfig { pc_history_len: 2, ..EmuConfig::default() }, &code[..3]);
+        assert_eq!(emu.pc_history(), vec![0x202, 0x206]);
+        let emu = run(EmuConfig { pc_history_len: 0, ..EmuConfig::default() }, &[asm_set_reg(0, 1)]);
+        assert!(emu.pc_history().is_empty());
+    }
+
+    #[test]
+    fn dxyn_can_leave_vf_alone_without_a_collision() {
+        let code = [asm_set_reg(0xF, 7), asm_draw(0, 0, 1)];
+        assert_eq!(run(EmuConfig { dxyn_preserve_vf_on_no_collision: true, ..EmuConfig::default() }, &code).v_reg[0xF], 7);
+        assert_eq!(run(EmuConfig::default(), &code).v_reg[0xF], 0);
+    }
+
+    #[test]
+    fn step_out_runs_until_the_subroutine_returns() {
+        // 200 calls 206, which sets V1 and V2 and returns to 202
+        let rom = program(&[asm_call(0x206), asm_set_reg(0, 1), asm_jump(0x204), asm_set_reg(1, 1), asm_set_reg(2, 2), asm_ret()]);
+        let mut emu = Emu::from_rom(&rom).unwrap();
+        // Not in a subroutine yet
+        assert!(!emu.step_out(10));
+        let mut emu = Emu::from_rom(&rom).unwrap();
+        emu.tick().unwrap();
+        emu.tick().unwrap();
+        assert!(emu.step_out(10));
+        assert_eq!((emu.pc, emu.v_reg[2], emu.v_reg[0]), (0x202, 2, 0));
+        let mut emu = Emu::from_rom(&rom).unwrap();
+        emu.tick().unwrap();
+        assert!(!emu.step_out(2));
+    }
+
+    #[test]
+    fn step_over_runs_a_whole_call() {
+        let rom = program(&[asm_call(0x206), asm_set_reg(0, 1), asm_jump(0x204), asm_set_reg(1, 1), asm_set_reg(2, 2), asm_ret()]);
+        let mut emu = Emu::from_rom(&rom).unwrap();
+        assert!(emu.step_over(10));
+        assert_eq!((emu.pc, emu.v_reg[1], emu.v_reg[2], emu.v_reg[0]), (0x202, 1, 2, 0));
+        // Anything but a call is a single step
+        assert!(emu.step_over(10));
+        assert_eq!(emu.pc, 0x204);
+        let mut emu = Emu::from_rom(&rom).unwrap();
+        assert!(!emu.step_over(3));
+    }
+
+    #[cfg(feature = "xo-chip")]
+    #[test]
+    fn xochip_cls_clears_only_the_selected_planes() {
+        // Both planes take one row of font 0: F0 into the first, 90 into
+        // the second. Then just the second plane is cleared
+        let mut emu = Emu::for_platform(Platform::XoChip);
+        emu.load_rom(&program(&[[0xF3, 0x01], asm_set_i(0), asm_draw(0, 0, 1), [0xF2, 0x01], asm_cls()])).unwrap();
+        for _ in 0..3 {
+            emu.tick().unwrap();
+        }
+        emu.present();
+        assert_eq!(&emu.get_plane(0).unwrap()[..8], &[true, true, true, true, false, false, false, false]);
+        assert_eq!(&emu.get_plane(1).unwrap()[..8], &[true, false, false, true, false, false, false, false]);
+        emu.tick().unwrap();
+        emu.tick().unwrap();
+        emu.present();
+        assert_eq!(&emu.get_plane(0).unwrap()[..4], &[true; 4]);
+        assert!(emu.get_plane(1).unwrap().iter().all(|&pixel| !pixel));
+        assert!(emu.get_plane(2).is_none());
+    }
+
+    #[test]
+    fn chip8_cls_clears_the_whole_screen() {
+        let mut emu = run(EmuConfig::default(), &[asm_draw(0, 0, 1), asm_cls()]);
+        emu.present();
+        assert!(emu.get_display().iter().all(|&pixel| !pixel));
+        let mut emu = Emu::from_rom(&[0xF1, 0x01]).unwrap();
+        assert!(emu.tick().is_err());
+    }
+
+    #[test]
+    fn drawing_past_the_rom_warns() {
+        // I points at the ROM's last two bytes, so 4 rows overrun it and
+        // 2 rows don't
+        let mut emu = Emu::from_rom(&program(&[asm_set_i(0x206), asm_draw(0, 0, 4), asm_draw(0, 0, 2), [0xFF, 0xFF]])).unwrap();
+        let events = record_events(&mut emu);
+        for _ in 0..3 {
+            emu.tick().unwrap();
+        }
+        let overruns: Vec<_> = events.borrow().iter().filter(|event| matches!(event, DebugEvent::SpriteOverrun { .. })).cloned().collect();
+        assert_eq!(overruns, vec![DebugEvent::SpriteOverrun { addr: 0x206, len: 4 }]);
+    }
+
+    #[test]
+    fn copy_display_into_checks_the_buffer_size() {
+        let mut emu = run(EmuConfig::default(), &[asm_draw(0, 0, 5)]);
+        emu.present();
+        let mut buf = vec![false; SCREEN_WIDTH * SCREEN_HEIGHT];
+        emu.copy_display_into(&mut buf).unwrap();
+        assert_eq!(&buf[..], emu.get_display());
+        let mut small = vec![false; 10];
+        assert_eq!(emu.copy_display_into(&mut small), Err(Chip8Error::BadBufferSize { size: 10, expected: 2048 }));
+    }
+
+    #[cfg(feature = "xo-chip")]
+    #[test]
+    fn auto_configure_applies_the_detected_preset() {
+        let mut emu = Emu::new();
+        emu.auto_configure(&program(&[[0x00, 0xFF], asm_jump(0x202)])).unwrap();
+        assert_eq!(emu.active_quirks(), EmuConfig::for_platform(Platform::SuperChip));
+        emu.tick().unwrap();
+        assert_eq!(emu.display_size(), (64, 32));
+        emu.present();
+        assert_eq!(emu.display_size(), (128, 64));
+        let mut emu = Emu::new();
+        emu.auto_configure(&program(&[[0xF1, 0x01]])).unwrap();
+        assert_eq!(emu.ram().len(), MAX_RAM_SIZE);
+    }
+
+    #[test]
+    fn running_into_zeroed_ram_reports_a_nop_run() {
+        let mut emu = Emu::with_config(EmuConfig { nop_run_threshold: Some(4), ..EmuConfig::default() });
+        let events = record_events(&mut emu);
+        // Three NOPs are under the threshold, the empty RAM at 0x300 isn't
+        emu.load_rom(&program(&[[0, 0], [0, 0], [0, 0], asm_set_reg(0, 1), asm_jump(0x300)])).unwrap();
+        for _ in 0..5 {
+            emu.tick().unwrap();
+        }
+        assert!(events.borrow().is_empty());
+        for _ in 0..10 {
+            emu.tick().unwrap();
+        }
+        assert_eq!(*events.borrow(), vec![DebugEvent::NopRun { addr: 0x306 }]);
+    }
+
+    #[test]
+    fn encoders_round_trip_through_the_decoder() {
+        assert_eq!(encode_draw(0, 1, 5), 0xD015);
+        assert_eq!(disassemble(encode_draw(0, 1, 5)), "DRW V0, V1, 5");
+        assert_eq!(disassemble(encode_call(0x456)), "CALL 0x456");
+        assert_eq!(disassemble(encode_skip_ne_reg(0xA, 0xB)), "SNE VA, VB");
+        assert_eq!(disassemble(encode_rand(3, 0x0F)), "RND V3, 0x0F");
+        assert_eq!(asm_skip_ne(3, 4), [0x43, 0x04]);
+        // Arguments too big for their field get masked
+        assert_eq!(encode_jump(0x1234), 0x1234);
+        assert_eq!(encode_shl(0x1A, 0x2B), 0x8ABE);
+
+        let encoded = [
+            (encode_nop(), "0000"),
+            (encode_cls(), "00E0"),
+            (encode_ret(), "00EE"),
+            (encode_scroll_down(4), "00CN"),
+            (encode_scroll_up(4), "00DN"),
+            (encode_scroll_right(), "00FB"),
+            (encode_scroll_left(), "00FC"),
+            (encode_exit(), "00FD"),
+            (encode_lores(), "00FE"),
+            (encode_hires(), "00FF"),
+            (encode_jump(0x345), "1NNN"),
+            (encode_call(0x345), "2NNN"),
+            (encode_skip_eq(0xA, 0x12), "3XNN"),
+            (encode_skip_ne(0xA, 0x12), "4XNN"),
+            (encode_skip_eq_reg(0xA, 0xB), "5XY0"),
+            (encode_save_range(0xA, 0xB), "5XY2"),
+            (encode_load_range(0xA, 0xB), "5XY3"),
+            (encode_set_reg(0xA, 0x12), "6XNN"),
+            (encode_add_reg(0xA, 0x12), "7XNN"),
+            (encode_copy_reg(0xA, 0xB), "8XY0"),
+            (encode_or(0xA, 0xB), "8XY1"),
+            (encode_and(0xA, 0xB), "8XY2"),
+            (encode_xor(0xA, 0xB), "8XY3"),
+            (encode_add_regs(0xA, 0xB), "8XY4"),
+            (encode_sub(0xA, 0xB), "8XY5"),
+            (encode_shr(0xA, 0xB), "8XY6"),
+            (encode_subn(0xA, 0xB), "8XY7"),
+            (encode_shl(0xA, 0xB), "8XYE"),
+            (encode_skip_ne_reg(0xA, 0xB), "9XY0"),
+            (encode_set_i(0x345), "ANNN"),
+            (encode_jump_v0(0x345), "BNNN"),
+            (encode_rand(0xA, 0x12), "CXNN"),
+            (encode_draw(0xA, 0xB, 5), "DXYN"),
+            (encode_skip_key(0xA), "EX9E"),
+            (encode_skip_not_key(0xA), "EXA1"),
+            (encode_long_i(), "F000"),
+            (encode_planes(3), "FN01"),
+            (encode_get_delay(0xA), "FX07"),
+            (encode_wait_key(0xA), "FX0A"),
+            (encode_set_delay(0xA), "FX15"),
+            (encode_set_sound(0xA), "FX18"),
+            (encode_add_i(0xA), "FX1E"),
+            (encode_font(0xA), "FX29"),
+            (encode_big_font(0xA), "FX30"),
+            (encode_bcd(0xA), "FX33"),
+            (encode_store(0xA), "FX55"),
+            (encode_load(0xA), "FX65"),
+            (encode_save_flags(0xA), "FX75"),
+            (encode_load_flags(0xA), "FX85"),
+        ];
+        for &(op, pattern) in &encoded {
+            assert_eq!(opcode_pattern(op), Some(pattern), "{:#06X}", op);
+            assert!(!disassemble(op).starts_with("DW"), "{:#06X}", op);
+        }
+        // and there's an encoder for every pattern the decoder knows
+        let mut patterns: Vec<&str> = (0..=u16::MAX).filter_map(opcode_pattern).collect();
+        patterns.sort();
+        patterns.dedup();
+        let mut covered: Vec<&str> = encoded.iter().map(|&(_, pattern)| pattern).collect();
+        covered.sort();
+        assert_eq!(covered, patterns);
+    }
+
+    #[test]
+    fn stack_depth_and_remaining_add_up() {
+        let mut emu = Emu::from_rom(&program(&[asm_call(0x202), asm_call(0x204)])).unwrap();
+        assert_eq!((emu.stack_depth(), emu.stack_remaining()), (0, 16));
+        emu.tick().unwrap();
+        emu.tick().unwrap();
+        assert_eq!((emu.stack_depth(), emu.stack_remaining()), (2, 14));
+    }
+
+    #[test]
+    fn shifts_into_vf_use_the_value_before_the_shift() {
+        // VF = 0x81 and V1 = 0x82. Shifting VF itself has to take the flag
+        // from 0x81, not from the shifted result that then gets overwritten
+        for (shift_uses_vy, op, vf) in [(false, asm_shr(0xF, 0), 1), (false, asm_shl(0xF, 0), 1), (true, asm_shr(0xF, 1), 0), (true, asm_shl(0xF, 1), 1)] {
+            let emu = run(EmuConfig { shift_uses_vy, ..EmuConfig::default() }, &[asm_set_reg(0xF, 0x81), asm_set_reg(1, 0x82), op]);
+            assert_eq!(emu.v_reg[0xF], vf, "{:02X}{:02X}", op[0], op[1]);
+        }
+        assert_eq!(asm_shr(0xF, 0), [0x8F, 0x06]);
+        assert_eq!(asm_shl(0xF, 0), [0x8F, 0x0E]);
+    }
+
+    #[test]
+    fn font_bytes_shows_the_loaded_font() {
+        let mut emu = Emu::new();
+        assert_eq!(emu.font_bytes(), &FONTSET[..]);
+        emu.set_fontset(&[1; FONTSET_SIZE]).unwrap();
+        assert_eq!(emu.font_bytes(), &[1; FONTSET_SIZE][..]);
+    }
+
+    #[test]
+    fn tick_fast_matches_plain_ticks() {
+        let rom = program(&[[0xC0, 0xFF], asm_add_reg(1, 3), asm_set_i(0x300), [0xF1, 0x33], asm_draw(0, 1, 3), asm_jump(0x200)]);
+        let mut slow = Emu::from_rom(&rom).unwrap();
+        slow.seed_rng(9);
+        let mut fast = Emu::from_rom(&rom).unwrap();
+        fast.seed_rng(9);
+        let events = record_events(&mut fast);
+        for _ in 0..500 {
+            slow.tick().unwrap();
+        }
+        fast.tick_fast(500).unwrap();
+        assert_eq!(slow.cpu_state(), fast.cpu_state());
+        assert_eq!(slow.state_hash(), fast.state_hash());
+        assert_eq!(fast.cycles(), 500);
+        // None of the debugging extras ran
+        assert!(events.borrow().is_empty());
+        assert!(!fast.is_dirty());
+        assert!(!fast.undo_last());
+    }
+
+    #[test]
+    fn tick_fast_still_starts_sounds() {
+        let starts = Rc::new(RefCell::new(Vec::new()));
+        let sink = starts.clone();
+        let mut emu = Emu::from_rom(&program(&[asm_set_reg(0, 6), asm_set_sound(0)])).unwrap();
+        emu.set_sound_start_callback(Box::new(move |duration| sink.borrow_mut().push(duration)));
+        emu.tick_fast(2).unwrap();
+        assert_eq!(*starts.borrow(), vec![6]);
+    }
+
+    #[test]
+    fn reset_mid_fx0a_wait_starts_clean() {
+        let rom = program(&[[0xF3, 0x0A], asm_set_reg(1, 1)]);
+        let mut emu = Emu::from_rom(&rom).unwrap();
+        for _ in 0..5 {
+            emu.tick().unwrap();
+        }
+        assert!(emu.is_waiting_for_key());
+        assert_eq!(emu.pc, 0x202);
+        emu.run_frame(1000).unwrap();
+        emu.reset();
+        assert!(!emu.is_waiting_for_key());
+        emu.load_rom(&program(&[asm_set_reg(2, 2)])).unwrap();
+        emu.tick().unwrap();
+        assert_eq!(emu.v_reg[2], 2);
+        // A key press ends the wait
+        let mut emu = Emu::from_rom(&rom).unwrap();
+        emu.tick().unwrap();
+        emu.tick().unwrap();
+        emu.keypress(7, true);
+        emu.tick().unwrap();
+        assert!(!emu.is_waiting_for_key());
+        assert_eq!(emu.v_reg[3], 7);
+        emu.tick().unwrap();
+        assert_eq!(emu.v_reg[1], 1);
+        // and so does undoing the FX0A
+        let mut emu = Emu::from_rom(&rom).unwrap();
+        emu.tick().unwrap();
+        assert!(emu.undo_last());
+        assert!(!emu.is_waiting_for_key());
+    }
+
+    #[test]
+    fn state_hash_tells_waiting_from_running() {
+        let mut emu = Emu::from_rom(&program(&[[0xF3, 0x0A]])).unwrap();
+        emu.tick().unwrap();
+        let waiting = emu.state_hash();
+        emu.waiting_for_key = None;
+        assert_ne!(emu.state_hash(), waiting);
+        let running = emu.state_hash();
+        emu.halted = true;
+        assert_ne!(emu.state_hash(), running);
+        emu.halted = false;
+        emu.rpl_flags[0] = 1;
+        assert_ne!(emu.state_hash(), running);
+    }
+
+    #[cfg(feature = "xo-chip")]
+    #[test]
+    fn xochip_f000_reads_a_big_endian_address() {
+        let mut emu = Emu::for_platform(Platform::XoChip);
+        emu.load_rom(&[0xF0, 0x00, 0x12, 0x34, 0x60, 0x01, 0x30, 0x01, 0xF0, 0x00, 0xAB, 0xCD, 0x61, 0x02]).unwrap();
+        emu.tick().unwrap();
+        assert_eq!((emu.i_reg, emu.pc), (0x1234, 0x204));
+        // A skip steps over all four bytes of F000 NNNN
+        emu.tick().unwrap();
+        emu.tick().unwrap();
+        assert_eq!(emu.pc, 0x20C);
+        emu.tick().unwrap();
+        assert_eq!((emu.i_reg, emu.v_reg[1]), (0x1234, 2));
+        let mut emu = Emu::from_rom(&[0xF0, 0x00]).unwrap();
+        assert!(emu.tick().is_err());
+    }
+
+    #[test]
+    fn f000_and_its_address_are_one_instruction() {
+        assert_eq!(be_u16(&[0x12, 0x34]), 0x1234);
+        assert_eq!(detect_platform(&[0xF0, 0x00, 0x12, 0x34]), Platform::XoChip);
+        assert_eq!(disassemble_rom(&[0xF0, 0x00, 0x12, 0x34], 0x200), vec![(0x200, 0xF000, "LD I, 0x1234".to_string())]);
+        // Without the address word there's nothing to show
+        assert_eq!(disassemble_rom(&[0xF0, 0x00, 0x12], 0x200), vec![
+            (0x200, 0xF000, "LD I, LONG".to_string()),
+            (0x202, 0x12, "DB 0x12".to_string()),
+        ]);
+        let emu = Emu::from_rom(&program(&[asm_long_i(), [0x12, 0x34], asm_cls()])).unwrap();
+        let next: Vec<_> = emu.instruction_iter().take(2).collect();
+        assert_eq!(next, vec![(0x200, 0xF000), (0x204, 0x00E0)]);
+    }
+
+    #[test]
+    fn display_ascii_draws_one_line_per_row() {
+        let mut font = [0; FONTSET_SIZE];
+        font[0] = 0x80;
+        let mut emu = Emu::new();
+        emu.set_fontset(&font).unwrap();
+        emu.load_rom(&program(&[asm_set_reg(0, 5), asm_set_reg(1, 2), asm_set_i(0), asm_draw(0, 1, 1)])).unwrap();
+        for _ in 0..4 {
+            emu.tick().unwrap();
+        }
+        emu.present();
+        let text = emu.display_ascii('#', '.');
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), SCREEN_HEIGHT);
+        assert!(lines.iter().all(|line| line.chars().count() == SCREEN_WIDTH));
+        assert_eq!(lines[2].find('#'), Some(5));
+        assert_eq!(text.matches('#').count(), 1);
+    }
+
+    #[test]
+    fn key_pressed_at_keeps_the_first_press_cycle() {
+        let mut emu = Emu::from_rom(&program(&[asm_jump(0x200)])).unwrap();
+        assert_eq!(emu.key_pressed_at(3), None);
+        for _ in 0..5 {
+            emu.tick().unwrap();
+        }
+        emu.keypress(3, true);
+        for _ in 0..5 {
+            emu.tick().unwrap();
+        }
+        // Still held, so this isn't a new press
+        emu.keypress(3, true);
+        assert_eq!(emu.key_pressed_at(3), Some(5));
+        emu.set_keys_bitmask(1 << 3 | 1 << 4);
+        assert_eq!((emu.key_pressed_at(3), emu.key_pressed_at(4)), (Some(5), Some(10)));
+        assert_eq!(emu.key_pressed_at(99), None);
+        emu.reset();
+        assert_eq!(emu.key_pressed_at(3), None);
+    }
+
+    #[test]
+    fn registers_shows_all_sixteen() {
+        let emu = run(EmuConfig::default(), &[asm_set_reg(0, 1), asm_set_reg(0xE, 0x42), asm_add_reg(0, 2)]);
+        let regs = emu.registers();
+        assert_eq!((regs[0], regs[0xE], regs[5]), (3, 0x42, 0));
+    }
+
+    #[test]
+    fn running_off_the_rom_halts_with_the_quirk() {
+        let mut emu = Emu::with_config(EmuConfig { halt_on_rom_end: true, ..EmuConfig::default() });
+        let events = record_events(&mut emu);
+        emu.load_rom(&program(&[asm_set_reg(0, 1), asm_set_reg(1, 1)])).unwrap();
+        for _ in 0..10 {
+            emu.tick().unwrap();
+        }
+        assert!(emu.is_halted());
+        assert_eq!(emu.cycles(), 2);
+        assert_eq!(*events.borrow(), vec![DebugEvent::RanOffRom { addr: 0x204 }]);
+        // Off by default, the zeroed RAM after the ROM just runs as NOPs
+        let emu = run(EmuConfig::default(), &[asm_set_reg(0, 1), [0, 0], [0, 0]]);
+        assert!(!emu.is_halted());
+    }
+
+    #[cfg(feature = "schip")]
+    #[test]
+    fn custom_screen_geometry_draws_and_doubles() {
+        let config = EmuConfig { screen_width: 64, screen_height: 48, ..EmuConfig::for_platform(Platform::SuperChip) };
+        let mut emu = Emu::with_config(config);
+        assert_eq!(emu.display_size(), (64, 48));
+        // A row at (10, 40), a row at (10, 10), then hires
+        emu.load_rom(&program(&[asm_set_reg(0, 10), asm_set_reg(1, 40), asm_set_i(0), asm_draw(0, 1, 1), asm_draw(0, 0, 1), asm_hires()])).unwrap();
+        for _ in 0..5 {
+            emu.tick().unwrap();
+        }
+        emu.present();
+        assert_eq!(emu.get_display().len(), 64 * 48);
+        let lit: Vec<usize> = (0..64 * 48).filter(|&idx| emu.get_display()[idx]).collect();
+        let row = |y: usize| (10..14).map(move |x| y * 64 + x);
+        assert_eq!(lit, row(10).chain(row(40)).collect::<Vec<_>>());
+        emu.tick().unwrap();
+        emu.present();
+        assert_eq!(emu.display_size(), (128, 96));
+        assert_eq!(Emu::new().display_size(), (64, 32));
+    }
+
+    #[test]
+    fn sound_start_callback_gets_the_duration() {
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let sink = events.clone();
+        let mut emu = Emu::from_rom(&program(&[asm_set_reg(0, 0), asm_set_sound(0), asm_set_reg(0, 6), asm_set_sound(0), asm_set_sound(0)])).unwrap();
+        emu.set_sound_start_callback(Box::new(move |duration| sink.borrow_mut().push(duration)));
+        for _ in 0..5 {
+            emu.tick().unwrap();
+        }
+        // ST = 0 is silent, and setting it again mid-tone doesn't restart it
+        assert_eq!(*events.borrow(), vec![6]);
+    }
+
+    #[test]
+    fn dump_ram_copies_all_of_ram() {
+        let emu = Emu::from_rom(&[1, 2, 3]).unwrap();
+        let dump = emu.dump_ram();
+        assert_eq!(&dump[..], emu.ram());
+        assert_eq!(&dump[..5], &[0xF0, 0x90, 0x90, 0x90, 0xF0]);
+        assert_eq!(&dump[0x200..0x203], &[1, 2, 3]);
+    }
+
+    #[test]
+    fn memory_wrap_decides_reads_past_the_end() {
+        // A 4 row sprite at I = 0xFFE: two rows from the end of RAM, then
+        // either the font's 0 from the start of RAM or the last byte again
+        for (memory_wrap, rows) in [(MemoryWrap::Wrap, [0x80, 0x80, 0xF0, 0x90]), (MemoryWrap::Clamp, [0x80, 0x80, 0x80, 0x80])] {
+            let mut emu = Emu::with_config(EmuConfig { memory_wrap, ..EmuConfig::default() });
+            emu.ram_mut()[0xFFE] = 0x80;
+            emu.ram_mut()[0xFFF] = 0x80;
+            emu.load_rom(&program(&[asm_set_i(0xFFE), asm_draw(0, 0, 4)])).unwrap();
+            emu.tick().unwrap();
+            emu.tick().unwrap();
+            emu.present();
+            for (y, byte) in rows.iter().enumerate() {
+                let expected: Vec<bool> = (0..4).map(|col| byte & (0x80 >> col) != 0).collect();
+                assert_eq!(emu.get_display()[y * SCREEN_WIDTH..y * SCREEN_WIDTH + 4], expected[..], "{:?} row {}", memory_wrap, y);
+            }
+        }
+    }
+
+    #[test]
+    fn any_key_pressed_sees_every_key() {
+        let mut emu = Emu::new();
+        assert!(!emu.any_key_pressed());
+        for key in 0..NUM_KEYS {
+            emu.keypress(key, true);
+            assert!(emu.any_key_pressed());
+            emu.keypress(key, false);
+            assert!(!emu.any_key_pressed());
+        }
+    }
+
+    #[test]
+    fn opcode_histogram_counts_by_high_nibble() {
+        let mut emu = Emu::from_rom(&program(&[asm_set_reg(0, 1), asm_jump(0x204), asm_jump(0x206), asm_jump(0x206)])).unwrap();
+        for _ in 0..100 {
+            emu.tick().unwrap();
+        }
+        let histogram = emu.opcode_histogram();
+        assert_eq!((histogram[1], histogram[6]), (99, 1));
+        assert_eq!(histogram.iter().sum::<u64>(), 100);
+        emu.reset();
+        assert_eq!(emu.opcode_histogram(), [0; 16]);
+    }
+
+    #[test]
+    fn load_segments_places_each_part() {
+        let loader = program(&[asm_set_reg(0, 1), asm_jump(0x400)]);
+        let game = program(&[asm_set_reg(1, 2)]);
+        let mut emu = Emu::new();
+        emu.load_segments(&[(0x300, &loader), (0x400, &game)]).unwrap();
+        assert_eq!(emu.pc, 0x300);
+        assert_eq!(&emu.ram()[0x300..0x304], &loader[..]);
+        assert_eq!(&emu.ram()[0x400..0x402], &game[..]);
+        for _ in 0..3 {
+            emu.tick().unwrap();
+        }
+        assert_eq!((emu.v_reg[0], emu.v_reg[1]), (1, 2));
+        assert_eq!(emu.loaded_rom().len(), 0x202);
+        // Nothing gets written when one of the segments doesn't fit
+        let mut emu = Emu::new();
+        assert_eq!(emu.load_segments(&[(0x300, &loader), (0xFFF, &game)]), Err(Chip8Error::OutOfBounds { addr: 0xFFF, len: 2 }));
+        assert_eq!(emu.ram()[0x300], 0);
+    }
+
+    #[test]
+    fn no_opcode_panics_from_any_state() {
+        // Every opcode, each from random registers, stack depth, PC and key
+        // state, on every platform and both memory wrap policies. Errors
+        // are fine, panics aren't
+        let mut rng = StdRng::seed_from_u64(194);
+        for platform in [Platform::Chip8, Platform::SuperChip, Platform::XoChip] {
+            for memory_wrap in [MemoryWrap::Wrap, MemoryWrap::Clamp] {
+                let mut emu = Emu::with_config(EmuConfig { memory_wrap, ..EmuConfig::for_platform(platform) });
+                let ram_len = emu.ram().len();
+                for op in 0..=u16::MAX {
+                    rng.fill(&mut emu.v_reg[..]);
+                    emu.i_reg = rng.gen();
+                    emu.sp = rng.gen_range(0, STACK_SIZE as u16 + 1);
+                    emu.pc = rng.gen_range(0, ram_len / 2) as u16 * 2;
+                    emu.halted = false;
+                    emu.waiting_for_key = None;
+                    emu.waiting_for_vblank = false;
+                    let pc = emu.pc as usize;
+                    emu.ram_mut()[pc..pc + 2].copy_from_slice(&op.to_be_bytes());
+                    emu.keys = [false; NUM_KEYS];
+                    if rng.gen() {
+                        emu.keypress(rng.gen_range(0, NUM_KEYS), true);
+                    }
+                    for _ in 0..3 {
+                        let _ = emu.tick();
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn supports_opcode_follows_the_platform() {
+        let mut emu = Emu::for_platform(Platform::Chip8);
+        assert!(!emu.supports_opcode(0x00FF));
+        assert!(emu.supports_opcode(0xD125));
+        assert!(!emu.supports_opcode(0x5121));
+        // A custom handler counts too
+        emu.register_opcode(0xF00F, 0x5001, |_, _| {});
+        assert!(emu.supports_opcode(0x5121));
+        let emu = Emu::for_platform(Platform::SuperChip);
+        assert_eq!(emu.supports_opcode(0x00FF), cfg!(feature = "schip"));
+        assert!(!emu.supports_opcode(0xF000));
+    }
+
+    #[test]
+    fn vip_draw_timing_charges_by_sprite_height() {
+        let cost = |rows: u8, vip_draw_timing: bool| {
+            let mut emu = Emu::with_config(EmuConfig { vip_draw_timing, ..EmuConfig::default() });
+            emu.load_rom(&program(&[asm_set_i(0x300), asm_draw(0, 0, rows)])).unwrap();
+            emu.tick().unwrap();
+            let before = emu.machine_cycles();
+            emu.tick().unwrap();
+            emu.machine_cycles() - before
+        };
+        assert!(cost(15, true) > cost(2, true));
+        assert_eq!(cost(15, false), cost(2, false));
+        assert_eq!(cost(1, false), cycle_cost(0xD001) as u64);
+    }
+
+    #[test]
+    fn display_delta_lists_changed_bytes() {
+        let mut emu = Emu::from_rom(&program(&[asm_set_reg(0, 9), asm_set_i(0x300), asm_draw(0, 0, 1)])).unwrap();
+        emu.ram_mut()[0x300] = 0x80;
+        let before = emu.packed_display();
+        assert_eq!(before.len(), SCREEN_WIDTH * SCREEN_HEIGHT / 8);
+        assert!(emu.display_delta(&before).is_empty());
+        for _ in 0..3 {
+            emu.tick().unwrap();
+        }
+        emu.present();
+        // One pixel at (9, 9)
+        assert_eq!(emu.display_delta(&before), vec![(9 * SCREEN_WIDTH / 8 + 1, 0x40)]);
+        assert_eq!(emu.display_delta(&[]).len(), 256);
+    }
+
+    #[test]
+    fn display_delta_indexes_past_u16_on_big_screens() {
+        // 1024x1024 packs into 128KB. Light the last pixel, which is in
+        // the last byte
+        let mut emu = Emu::with_config(EmuConfig { screen_width: 1024, screen_height: 1024, ..EmuConfig::default() });
+        let before = emu.packed_display();
+        assert!(emu.set_pixel(1023, 1023, true));
+        emu.present();
+        assert_eq!(emu.display_delta(&before), vec![(1024 * 1024 / 8 - 1, 0x01)]);
+    }
+
+    #[test]
+    fn reset_keeps_the_font_and_config() {
+        let font = [0xAA; FONTSET_SIZE];
+        let mut emu = Emu::with_config(EmuConfig { shift_uses_vy: true, ..EmuConfig::default() });
+        emu.set_fontset(&font).unwrap();
+        emu.load_rom(&program(&[asm_set_reg(3, 7)])).unwrap();
+        emu.tick().unwrap();
+        emu.reset();
+        assert_eq!(emu.font_bytes(), &font[..]);
+        assert_eq!(emu.registers(), [0; NUM_REGS]);
+        assert!(emu.active_quirks().shift_uses_vy);
+    }
+
+    #[test]
+    fn capabilities_lists_the_enabled_features() {
+        let features = capabilities();
+        assert_eq!(features.contains(&"schip"), cfg!(feature = "schip"));
+        assert_eq!(features.contains(&"xo-chip"), cfg!(feature = "xo-chip"));
+        assert_eq!(features.contains(&"octo"), cfg!(feature = "octo"));
+        assert_eq!(VERSION, env!("CARGO_PKG_VERSION"));
+    }
+
+    #[cfg(feature = "schip")]
+    #[test]
+    fn tick_outcome_reports_side_effects() {
+        let mut emu = Emu::for_platform(Platform::SuperChip);
+        emu.load_rom(&program(&[asm_set_reg(1, 5), asm_draw(0, 0, 1), asm_set_sound(1), asm_exit()])).unwrap();
+        assert_eq!(emu.tick_outcome().unwrap(), TickOutcome::default());
+        let outcome = emu.tick_outcome().unwrap();
+        assert!(outcome.drew && !outcome.beep_started && !outcome.halted);
+        let outcome = emu.tick_outcome().unwrap();
+        assert!(!outcome.drew && outcome.beep_started);
+        assert!(emu.tick_outcome().unwrap().halted);
+        assert_eq!(emu.tick_outcome().unwrap(), TickOutcome { halted: true, ..TickOutcome::default() });
+    }
+
+    #[test]
+    fn set_pixel_draws_without_dxyn() {
+        let mut emu = Emu::new();
+        assert!(emu.set_pixel(3, 2, true));
+        // It goes to the back buffer like any draw
+        assert!(!emu.get_display()[2 * SCREEN_WIDTH + 3]);
+        assert!(emu.is_dirty());
+        emu.present();
+        assert!(emu.get_display()[2 * SCREEN_WIDTH + 3]);
+        assert!(emu.set_pixel(3, 2, false));
+        emu.present();
+        assert!(emu.get_display().iter().all(|&pixel| !pixel));
+        assert!(!emu.set_pixel(SCREEN_WIDTH, 0, true));
+        assert!(!emu.set_pixel(0, SCREEN_HEIGHT, true));
+    }
+}
@@ -0,0 +1,247 @@
+// OCTO STATE IMPORT
+//
+// Loads a machine state saved by Octo (https://github.com/JohnEarnest/Octo),
+// the reference CHIP-8/SCHIP/XO-CHIP environment, so we can start from the
+// exact same point and cross-check execution against it.
+//
+// Octo keeps its emulator state in a plain JS object, which serializes to
+// JSON like this (every field is optional, missing ones are left alone):
+//
+//     Octo field    Emu field     Notes
+//     ----------    ---------     -----
+//     "pc"          pc            number
+//     "i"           i_reg         number
+//     "v"           v_reg         array of 16 numbers
+//     "r"           stack, sp     return stack array, sp = its length
+//     "dt"          dt            number
+//     "st"          st            number
+//     "m"           ram           array of bytes, copied from address 0
+//
+// Only the subset of JSON Octo actually produces is understood, which keeps
+// this feature free of extra dependencies
+
+use crate::{Emu, NUM_REGS, STACK_SIZE};
+
+// Strings, booleans and null only ever show up in fields we ignore, so they
+// all collapse into Other
+enum Json {
+    Number(f64),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+    Other,
+}
+
+struct Parser<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn skip_whitespace(&mut self) {
+        while self.pos < self.bytes.len() && self.bytes[self.pos].is_ascii_whitespace() {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_whitespace();
+        self.bytes.get(self.pos).copied()
+    }
+
+    fn expect(&mut self, c: u8) -> Result<(), String> {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(format!("Expected '{}' at offset {}", c as char, self.pos))
+        }
+    }
+
+    fn parse_value(&mut self) -> Result<Json, String> {
+        match self.peek() {
+            Some(b'{') => self.parse_object(),
+            Some(b'[') => self.parse_array(),
+            Some(b'"') => self.parse_string().map(|_| Json::Other),
+            Some(b't') => self.parse_literal("true"),
+            Some(b'f') => self.parse_literal("false"),
+            Some(b'n') => self.parse_literal("null"),
+            Some(_) => self.parse_number(),
+            None => Err("Unexpected end of input".to_string()),
+        }
+    }
+
+    fn parse_literal(&mut self, word: &str) -> Result<Json, String> {
+        if self.bytes[self.pos..].starts_with(word.as_bytes()) {
+            self.pos += word.len();
+            Ok(Json::Other)
+        } else {
+            Err(format!("Unexpected token at offset {}", self.pos))
+        }
+    }
+
+    fn parse_number(&mut self) -> Result<Json, String> {
+        let start = self.pos;
+        while self.pos < self.bytes.len()
+            && matches!(self.bytes[self.pos], b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9')
+        {
+            self.pos += 1;
+        }
+        let text = std::str::from_utf8(&self.bytes[start..self.pos]).unwrap_or("");
+        text.parse::<f64>()
+            .map(Json::Number)
+            .map_err(|_| format!("Invalid number at offset {}", start))
+    }
+
+    fn parse_string(&mut self) -> Result<String, String> {
+        self.expect(b'"')?;
+        let mut out = String::new();
+        loop {
+            let c = *self.bytes.get(self.pos).ok_or("Unterminated string")?;
+            self.pos += 1;
+            match c {
+                b'"' => return Ok(out),
+                b'\\' => {
+                    // Octo never writes anything fancier than simple escapes
+                    let escaped = *self.bytes.get(self.pos).ok_or("Unterminated string")?;
+                    self.pos += 1;
+                    out.push(match escaped {
+                        b'n' => '\n',
+                        b't' => '\t',
+                        other => other as char,
+                    });
+                },
+                _ => out.push(c as char),
+            }
+        }
+    }
+
+    fn parse_array(&mut self) -> Result<Json, String> {
+        self.expect(b'[')?;
+        let mut items = Vec::new();
+        if self.peek() == Some(b']') {
+            self.pos += 1;
+            return Ok(Json::Array(items));
+        }
+        loop {
+            items.push(self.parse_value()?);
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b']') => {
+                    self.pos += 1;
+                    return Ok(Json::Array(items));
+                },
+                _ => return Err(format!("Expected ',' or ']' at offset {}", self.pos)),
+            }
+        }
+    }
+
+    fn parse_object(&mut self) -> Result<Json, String> {
+        self.expect(b'{')?;
+        let mut fields = Vec::new();
+        if self.peek() == Some(b'}') {
+            self.pos += 1;
+            return Ok(Json::Object(fields));
+        }
+        loop {
+            self.skip_whitespace();
+            let key = self.parse_string()?;
+            self.expect(b':')?;
+            fields.push((key, self.parse_value()?));
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b'}') => {
+                    self.pos += 1;
+                    return Ok(Json::Object(fields));
+                },
+                _ => return Err(format!("Expected ',' or '}}' at offset {}", self.pos)),
+            }
+        }
+    }
+}
+
+fn as_number(value: &Json, field: &str, max: f64) -> Result<f64, String> {
+    match value {
+        Json::Number(n) if *n >= 0.0 && *n <= max && n.fract() == 0.0 => Ok(*n),
+        _ => Err(format!("Field \"{}\" is not a valid number", field)),
+    }
+}
+
+fn as_array<'a>(value: &'a Json, field: &str) -> Result<&'a [Json], String> {
+    match value {
+        Json::Array(items) => Ok(items),
+        _ => Err(format!("Field \"{}\" is not an array", field)),
+    }
+}
+
+impl Emu {
+    pub fn import_octo_state(&mut self, json: &str) -> Result<(), String> {
+        // Applies an Octo state dump on top of the current state. Nothing is
+        // modified unless the whole document parses and every field is valid
+        let mut parser = Parser { bytes: json.as_bytes(), pos: 0 };
+        let fields = match parser.parse_value()? {
+            Json::Object(fields) => fields,
+            _ => return Err("Octo state must be a JSON object".to_string()),
+        };
+
+        // Work on copies so a bad field halfway through doesn't leave us
+        // with a half-imported state
+        let mut pc = self.pc;
+        let mut i_reg = self.i_reg;
+        let mut v_reg = self.v_reg;
+        let mut stack = self.stack;
+        let mut sp = self.sp;
+        let mut dt = self.dt;
+        let mut st = self.st;
+        let mut ram = self.ram.clone();
+        let max_addr = (ram.len() - 1) as f64;
+
+        for (key, value) in &fields {
+            match key.as_str() {
+                "pc" => pc = as_number(value, key, max_addr)? as u16,
+                "i" => i_reg = as_number(value, key, u16::MAX as f64)? as u16,
+                "dt" => dt = as_number(value, key, 255.0)? as u8,
+                "st" => st = as_number(value, key, 255.0)? as u8,
+                "v" => {
+                    let items = as_array(value, key)?;
+                    if items.len() != NUM_REGS {
+                        return Err(format!("Field \"v\" must have {} entries", NUM_REGS));
+                    }
+                    for (idx, item) in items.iter().enumerate() {
+                        v_reg[idx] = as_number(item, key, 255.0)? as u8;
+                    }
+                },
+                "r" => {
+                    let items = as_array(value, key)?;
+                    if items.len() > STACK_SIZE {
+                        return Err(format!("Field \"r\" has more than {} entries", STACK_SIZE));
+                    }
+                    for (idx, item) in items.iter().enumerate() {
+                        stack[idx] = as_number(item, key, max_addr)? as u16;
+                    }
+                    sp = items.len() as u16;
+                },
+                "m" => {
+                    let items = as_array(value, key)?;
+                    if items.len() > ram.len() {
+                        return Err("Field \"m\" is larger than RAM".to_string());
+                    }
+                    for (idx, item) in items.iter().enumerate() {
+                        ram[idx] = as_number(item, key, 255.0)? as u8;
+                    }
+                },
+                // Octo stores plenty of UI and quirk settings we don't need
+                _ => (),
+            }
+        }
+
+        self.pc = pc;
+        self.i_reg = i_reg;
+        self.v_reg = v_reg;
+        self.stack = stack;
+        self.sp = sp;
+        self.dt = dt;
+        self.st = st;
+        self.ram = ram;
+        Ok(())
+    }
+}
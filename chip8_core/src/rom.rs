@@ -0,0 +1,260 @@
+// ROM ANALYSIS HELPERS
+//
+// Static helpers that look at raw ROM bytes without needing an Emu
+
+use crate::{be_u16, nnn, sprite_pixel, Emu, Platform};
+
+pub fn extract_strings(rom: &[u8], min_len: usize) -> Vec<String> {
+    // Scan for runs of printable ASCII bytes at least min_len long.
+    // Many ROMs embed their title or author this way, so this is handy
+    // for a ROM browser
+    let mut strings = Vec::new();
+    let mut current = String::new();
+
+    for byte in rom {
+        if byte.is_ascii_graphic() || *byte == b' ' {
+            current.push(*byte as char);
+        } else {
+            if current.len() >= min_len && !current.is_empty() {
+                strings.push(current.clone());
+            }
+            current.clear();
+        }
+    }
+
+    // Don't forget a string running right up to the end of the ROM
+    if current.len() >= min_len && !current.is_empty() {
+        strings.push(current);
+    }
+
+    strings
+}
+
+pub fn opcode_pattern(op: u16) -> Option<&'static str> {
+    // Maps an opcode to the pattern it's documented under (e.g. 0x6A12 is
+    // "6XNN"), or None if the interpreter has no instruction for it on any
+    // platform. This needs to stay in sync with the match in Emu::execute
+    let digit1 = (op & 0xF000) >> 12;
+    let digit2 = (op & 0x0F00) >> 8;
+    let digit3 = (op & 0x00F0) >> 4;
+    let digit4 = op & 0x000F;
+
+    let pattern = match (digit1, digit2, digit3, digit4) {
+        (0,0,0,0) => "0000",
+        (0,0,0xE,0) => "00E0",
+        (0,0,0xE,0xE) => "00EE",
+        (0,0,0xC,_) => "00CN",
+        (0,0,0xD,_) => "00DN",
+        (0,0,0xF,0xB) => "00FB",
+        (0,0,0xF,0xC) => "00FC",
+        (0,0,0xF,0xD) => "00FD",
+        (0,0,0xF,0xE) => "00FE",
+        (0,0,0xF,0xF) => "00FF",
+        (1,_,_,_) => "1NNN",
+        (2,_,_,_) => "2NNN",
+        (3,_,_,_) => "3XNN",
+        (4,_,_,_) => "4XNN",
+        (5,_,_,0) => "5XY0",
+        (5,_,_,2) => "5XY2",
+        (5,_,_,3) => "5XY3",
+        (6,_,_,_) => "6XNN",
+        (7,_,_,_) => "7XNN",
+        (8,_,_,0) => "8XY0",
+        (8,_,_,1) => "8XY1",
+        (8,_,_,2) => "8XY2",
+        (8,_,_,3) => "8XY3",
+        (8,_,_,4) => "8XY4",
+        (8,_,_,5) => "8XY5",
+        (8,_,_,6) => "8XY6",
+        (8,_,_,7) => "8XY7",
+        (8,_,_,0xE) => "8XYE",
+        (9,_,_,0) => "9XY0",
+        (0xA,_,_,_) => "ANNN",
+        (0xB,_,_,_) => "BNNN",
+        (0xC,_,_,_) => "CXNN",
+        (0xD,_,_,_) => "DXYN",
+        (0xE,_,9,0xE) => "EX9E",
+        (0xE,_,0xA,1) => "EXA1",
+        (0xF,0,0,0) => "F000",
+        (0xF,_,0,1) => "FN01",
+        (0xF,_,0,7) => "FX07",
+        (0xF,_,0,0xA) => "FX0A",
+        (0xF,_,1,5) => "FX15",
+        (0xF,_,1,8) => "FX18",
+        (0xF,_,1,0xE) => "FX1E",
+        (0xF,_,2,9) => "FX29",
+        (0xF,_,3,0) => "FX30",
+        (0xF,_,3,3) => "FX33",
+        (0xF,_,5,5) => "FX55",
+        (0xF,_,6,5) => "FX65",
+        (0xF,_,7,5) => "FX75",
+        (0xF,_,8,5) => "FX85",
+        (_,_,_,_) => return None,
+    };
+    Some(pattern)
+}
+
+pub fn opcode_platform(op: u16) -> Option<Platform> {
+    // The first platform in the CHIP-8 family to have this instruction, or
+    // None if no platform has it
+    let platform = match opcode_pattern(op)? {
+        "00CN" | "00FB" | "00FC" | "00FD" | "00FE" | "00FF"
+            | "FX30" | "FX75" | "FX85" => Platform::SuperChip,
+        "00DN" | "5XY2" | "5XY3" | "F000" | "FN01" => Platform::XoChip,
+        _ => Platform::Chip8,
+    };
+    Some(platform)
+}
+
+pub fn detect_platform(rom: &[u8]) -> Platform {
+    // Best guess at which platform a ROM was written for: the newest one
+    // any of its opcodes needs (00FF means SCHIP, 5XY2 means XO-CHIP...).
+    // Only a guess, since data bytes can look like newer opcodes and a
+    // SCHIP game may never happen to use a SCHIP only instruction
+    rom.chunks_exact(2)
+        .filter_map(|pair| opcode_platform(be_u16(pair)))
+        .max()
+        .unwrap_or(Platform::Chip8)
+}
+
+pub(crate) fn platform_built(platform: Platform) -> bool {
+    // Whether this build was compiled with the instructions `platform`
+    // adds, see the schip and xo-chip features
+    match platform {
+        Platform::Chip8 => true,
+        Platform::SuperChip => cfg!(feature = "schip"),
+        Platform::XoChip => cfg!(feature = "xo-chip"),
+    }
+}
+
+pub fn implemented_opcodes() -> Vec<&'static str> {
+    // Every opcode pattern this build can run (given a new enough
+    // Platform in the EmuConfig), in opcode order. Built by asking
+    // opcode_pattern about every opcode so the two can't disagree
+    let mut patterns = Vec::new();
+    for op in 0..=u16::MAX {
+        if let (Some(pattern), Some(platform)) = (opcode_pattern(op), opcode_platform(op)) {
+            if platform_built(platform) && !patterns.contains(&pattern) {
+                patterns.push(pattern);
+            }
+        }
+    }
+    patterns
+}
+
+pub fn validate_rom(rom: &[u8]) -> Result<(), Vec<(u16, u16)>> {
+    // Walks the ROM two bytes at a time and collects the (address, opcode)
+    // of everything that doesn't decode to a known instruction.
+    // This is only a heuristic! Sprite data and other constants live right
+    // alongside the code, so a perfectly good ROM can still get flagged.
+    // Treat the result as a list of warnings, not proof the ROM is broken.
+    // A trailing odd byte can't be an instruction and is skipped. Input too
+    // big to load still gets checked, its addresses just wrap around
+    let mut unknown = Vec::new();
+
+    for (i, pair) in rom.chunks_exact(2).enumerate() {
+        let op = be_u16(pair);
+        if opcode_pattern(op).is_none() {
+            let addr = crate::START_ADDR.wrapping_add((i * 2) as u16);
+            unknown.push((addr, op));
+        }
+    }
+
+    if unknown.is_empty() {
+        Ok(())
+    } else {
+        Err(unknown)
+    }
+}
+
+pub fn sprite_bounds(x: u8, y: u8, sprite: &[u8]) -> (u8, u8, u8, u8) {
+    // Tight (min_x, min_y, max_x, max_y) box around the lit pixels of a
+    // sprite drawn at (x, y), with the same bit layout DXYN uses. This
+    // doesn't know the screen size, so nothing wraps around (coordinates
+    // just stop at 255).
+    // A sprite with no lit pixels just gives back (x, y, x, y)
+    let mut bounds: Option<(u8, u8, u8, u8)> = None;
+
+    for (row, pixels) in sprite.iter().enumerate() {
+        for col in 0..8 {
+            if sprite_pixel(*pixels as u16, 8, col) {
+                let px = x.saturating_add(col as u8);
+                let py = y.saturating_add(row as u8);
+                bounds = Some(match bounds {
+                    None => (px, py, px, py),
+                    Some((min_x, min_y, max_x, max_y)) => {
+                        (min_x.min(px), min_y.min(py), max_x.max(px), max_y.max(py))
+                    },
+                });
+            }
+        }
+    }
+
+    bounds.unwrap_or((x, y, x, y))
+}
+
+// How far back (in bytes) a jump can go and still count as a "tight" loop
+const TIGHT_LOOP_LEN: usize = 16;
+
+pub fn detects_display_wait(rom: &[u8]) -> bool {
+    // Guesses whether a ROM relies on the display wait quirk, by looking
+    // for a short loop (a 1NNN jumping at most a few instructions back)
+    // with a DXYN inside. Games that draw in a tight loop like that tend to
+    // be paced by the VIP drawing once per frame, and run way too fast
+    // without it.
+    // This is only a guess: the scan treats sprite data as code, can't
+    // follow BNNN or subroutine calls, and some perfectly fast-running
+    // ROMs loop tightly around a draw on purpose, so expect false positives
+    let ops: Vec<u16> = rom
+        .chunks_exact(2)
+        .map(be_u16)
+        .collect();
+
+    for (i, op) in ops.iter().enumerate() {
+        if op & 0xF000 != 0x1000 {
+            continue;
+        }
+        // usize, since a ROM too big to load would run past 0xFFFF
+        let jump_addr = crate::START_ADDR as usize + i * 2;
+        let target = nnn(*op) as usize;
+        if target > jump_addr || jump_addr - target > TIGHT_LOOP_LEN || target < crate::START_ADDR as usize {
+            continue;
+        }
+
+        // Is there a draw anywhere between the jump target and the jump?
+        let first = (target - crate::START_ADDR as usize) / 2;
+        if ops[first..=i].iter().any(|op| op & 0xF000 == 0xD000) {
+            return true;
+        }
+    }
+    false
+}
+
+pub fn behaviorally_equal(rom_a: &[u8], rom_b: &[u8], cycles: usize, seed: u64) -> bool {
+    // Runs both ROMs side by side for `cycles` ticks, with the same RNG
+    // seed and no input, and checks the state hashes match after every
+    // step. Meant for checking a patched ROM still does the same thing.
+    // RAM is part of the state, so a patch to data that the ROM never
+    // reads still counts as a difference
+    let (mut a, mut b) = match (Emu::from_rom(rom_a), Emu::from_rom(rom_b)) {
+        (Ok(a), Ok(b)) => (a, b),
+        _ => return false,
+    };
+    a.seed_rng(seed);
+    b.seed_rng(seed);
+
+    if a.state_hash() != b.state_hash() {
+        return false;
+    }
+    for _ in 0..cycles {
+        let (result_a, result_b) = (a.tick(), b.tick());
+        if result_a != result_b || a.state_hash() != b.state_hash() {
+            return false;
+        }
+        // Both stopped on the same error, there's nothing more to compare
+        if result_a.is_err() {
+            break;
+        }
+    }
+    true
+}
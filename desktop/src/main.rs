@@ -39,13 +39,17 @@ fn main() {
 
     let mut event_pump = sdl_context.event_pump().unwrap();
 
-    // Initialize Emu object
-    let mut chip8 = Emu::new();
-        
+    // Initialize Emu object with the game loaded
     let mut rom = File::open(&args[1]).expect("Unable to open file");
     let mut buffer = Vec::new();
     rom.read_to_end(&mut buffer).unwrap();
-    chip8.load(&buffer);
+    let mut chip8 = match Emu::from_rom(&buffer) {
+        Ok(emu) => emu,
+        Err(e) => {
+            println!("Unable to load ROM: {}", e);
+            return;
+        },
+    };
     
 
     'gameloop: loop {
@@ -69,9 +73,13 @@ fn main() {
         }
 
         for _ in 0..TICKS_PER_FRAME {
-            chip8.tick();
+            if let Err(e) = chip8.tick() {
+                println!("Emulation stopped: {}", e);
+                break 'gameloop;
+            }
         }
         chip8.tick_timers();
+        chip8.present();
         draw_screen(&chip8, &mut canvas);
     }
 
@@ -90,17 +98,20 @@ fn draw_screen(emu: &Emu, canvas: &mut Canvas<Window>) {
    canvas.clear();
 
    let screen_buf = emu.get_display();
+   // The window is sized for lores, so hires pixels are drawn smaller
+   let (width, _) = emu.display_size();
+   let scale = WINDOW_WIDTH / width as u32;
    // Now set draw color to white
    // Iterate through each point and see if it should be drawn
    canvas.set_draw_color(Color::RGB(255, 255, 255));
    for (i,pixel) in screen_buf.iter().enumerate() {
        if *pixel {
            // Convert 1D array's index into a 2D (x,y) position
-           let x = (i % SCREEN_WIDTH) as u32;
-           let y = (i / SCREEN_WIDTH) as u32;
+           let x = (i % width) as u32;
+           let y = (i / width) as u32;
 
-           // Draw a rectangle at (x,y), scaled by the SCALE value
-           let rect = Rect::new((x * SCALE) as i32, (y * SCALE) as i32, SCALE, SCALE);
+           // Draw a rectangle at (x,y), scaled to fit the window
+           let rect = Rect::new((x * scale) as i32, (y * scale) as i32, scale, scale);
            canvas.fill_rect(rect).unwrap();
        }
    }